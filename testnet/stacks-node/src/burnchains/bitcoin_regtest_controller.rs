@@ -280,7 +280,7 @@ impl BitcoinRegtestController {
 
     fn build_leader_key_register_tx(&mut self, payload: LeaderKeyRegisterOp, signer: &mut BurnchainOpSigner) -> Option<Transaction> {
         
-        let public_key = signer.get_public_key();
+        let public_key = signer.get_public_key()?;
 
         let (mut tx, utxos) = self.prepare_tx(&public_key, DUST_UTXO_LIMIT)?;
 
@@ -332,7 +332,7 @@ impl BitcoinRegtestController {
 
     fn build_leader_block_commit_tx(&mut self, payload: LeaderBlockCommitOp, signer: &mut BurnchainOpSigner) -> Option<Transaction> {
 
-        let public_key = signer.get_public_key();
+        let public_key = signer.get_public_key()?;
 
         let (mut tx, utxos) = self.prepare_tx(&public_key, payload.burn_fee)?;
 
@@ -429,7 +429,7 @@ impl BitcoinRegtestController {
 
         // Append the change output
         let total_unspent: u64 = utxos.iter().map(|o| o.amount).sum();
-        let public_key = signer.get_public_key();
+        let public_key = signer.get_public_key()?;
         let change_address_hash = Hash160::from_data(&public_key.to_bytes()).to_bytes();
         if total_unspent < total_spent + tx_fee {
             warn!("Unspent total {} is less than intended spend: {}",
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use super::{BurnchainController, BurnchainControllerError, BurnchainTip, MocknetController};
+use super::super::Keychain;
+use super::super::operations::BurnchainOpSigner;
+use crate::run_loop::RegisteredKey;
+
+use stacks::burnchains::{BurnchainHeaderHash, Txid};
+use stacks::chainstate::burn::{BlockHeaderHash, BlockSnapshot, VRFSeed};
+use stacks::chainstate::burn::operations::{
+    LeaderBlockCommitOp,
+    LeaderKeyRegisterOp,
+    BlockstackOperationType,
+};
+use stacks::util::hash::Sha256Sum;
+use stacks::util::vrf::VRFPublicKey;
+
+/// MockMiner drives a `MocknetController` the way a leader would:
+/// it registers VRF keys and spends them on block commits, one commit per round.
+pub struct MockMiner {
+    keychain: Keychain,
+    op_signer: BurnchainOpSigner,
+    burn_fee: u64,
+    /// Register a new key whenever the miner would otherwise be left without one.
+    pub auto_renew_keys: bool,
+    available_keys: VecDeque<RegisteredKey>,
+    pending_keys: Vec<VRFPublicKey>,
+    commits_submitted: u64,
+}
+
+impl MockMiner {
+
+    pub fn new(keychain: Keychain, burn_fee: u64) -> MockMiner {
+        let op_signer = keychain.generate_op_signer();
+        MockMiner {
+            keychain,
+            op_signer,
+            burn_fee,
+            auto_renew_keys: false,
+            available_keys: VecDeque::new(),
+            pending_keys: vec![],
+            commits_submitted: 0,
+        }
+    }
+
+    /// Mined keys that have not been consumed by a commit yet.
+    pub fn available_keys(&self) -> &VecDeque<RegisteredKey> {
+        &self.available_keys
+    }
+
+    /// Submit a key register op; the key becomes available once it is mined.
+    pub fn register_key(&mut self, controller: &mut MocknetController) {
+        let burnchain_tip = controller.get_chain_tip();
+        let vrf_public_key = self.keychain.rotate_vrf_keypair(burnchain_tip.block_snapshot.block_height);
+        let op = BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
+            public_key: vrf_public_key.clone(),
+            memo: vec![],
+            address: self.keychain.get_address(),
+            consensus_hash: burnchain_tip.block_snapshot.consensus_hash.clone(),
+            vtxindex: 0,
+            txid: Txid([0u8; 32]),
+            block_height: 0,
+            burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+        });
+        if controller.submit_operation(op, &mut self.op_signer) {
+            self.pending_keys.push(vrf_public_key);
+        }
+    }
+
+    /// Commit with the oldest available key (if any), renew keys if enabled, then mine a block.
+    pub fn simulate_round(&mut self, controller: &mut MocknetController) -> BurnchainTip {
+        let burnchain_tip = controller.get_chain_tip();
+
+        let committed = match self.available_keys.front().cloned() {
+            Some(key) => {
+                let (parent_block_ptr, parent_vtxindex) = match burnchain_tip.get_winning_tx_index() {
+                    Some(winning_tx_vtindex) => (burnchain_tip.block_snapshot.block_height as u32, winning_tx_vtindex as u16),
+                    None => (0, 0)
+                };
+                let op = self.generate_block_commit_op(&key, parent_block_ptr, parent_vtxindex);
+                controller.submit_operation(op, &mut self.op_signer)
+            },
+            None => false
+        };
+
+        // A successful commit will consume its key, so only the remaining ones count.
+        let keys_left = self.available_keys.len() - (committed as usize) + self.pending_keys.len();
+        if self.auto_renew_keys && keys_left == 0 {
+            self.register_key(controller);
+        }
+
+        let new_tip = controller.sync();
+        self.process_burnchain_state(&new_tip);
+        new_tip
+    }
+
+    /// Block commit with the oldest available key that builds on the block which won the
+    /// sortition of `snapshot`, rather than on the chain tip; mined, it forks the Stacks chain
+    /// at `snapshot`.  Builds on genesis if `snapshot` had no sortition, and returns `None`
+    /// if the miner has no key available.
+    pub fn against_snapshot(&mut self, controller: &MocknetController, snapshot: &BlockSnapshot) -> Result<Option<BlockstackOperationType>, BurnchainControllerError> {
+        let key = match self.available_keys.front().cloned() {
+            Some(key) => key,
+            None => return Ok(None)
+        };
+        let (parent_block_ptr, parent_vtxindex) = match controller.winning_commit_at(snapshot)? {
+            Some(commit) => (commit.block_height as u32, commit.vtxindex as u16),
+            None => (0, 0)
+        };
+        Ok(Some(self.generate_block_commit_op(&key, parent_block_ptr, parent_vtxindex)))
+    }
+
+    /// Pick up newly mined keys and drop the keys consumed by commits in this block.
+    fn process_burnchain_state(&mut self, burnchain_tip: &BurnchainTip) {
+        for op in burnchain_tip.state_transition.accepted_ops.iter() {
+            if let BlockstackOperationType::LeaderKeyRegister(ref op) = op {
+                if let Some(index) = self.pending_keys.iter().position(|pk| pk == &op.public_key) {
+                    self.pending_keys.remove(index);
+                    self.available_keys.push_back(RegisteredKey {
+                        vrf_public_key: op.public_key.clone(),
+                        block_height: op.block_height,
+                        op_vtxindex: op.vtxindex,
+                    });
+                }
+            }
+        }
+
+        for consumed in burnchain_tip.state_transition.consumed_leader_keys.iter() {
+            self.available_keys.retain(|key| key.vrf_public_key != consumed.public_key);
+        }
+    }
+
+    fn generate_block_commit_op(&mut self, key: &RegisteredKey, parent_block_ptr: u32, parent_vtxindex: u16) -> BlockstackOperationType {
+        self.commits_submitted += 1;
+        let block_header_hash = BlockHeaderHash(Sha256Sum::from_data(
+            format!("{}::{}", key.vrf_public_key.to_hex(), self.commits_submitted).as_bytes()).0);
+
+        BlockstackOperationType::LeaderBlockCommit(LeaderBlockCommitOp {
+            block_header_hash,
+            burn_fee: self.burn_fee,
+            input: self.keychain.get_burnchain_signer(),
+            key_block_ptr: key.block_height as u32,
+            key_vtxindex: key.op_vtxindex as u16,
+            memo: vec![],
+            new_seed: VRFSeed(block_header_hash.0),
+            parent_block_ptr,
+            parent_vtxindex,
+            vtxindex: 0,
+            txid: Txid([0u8; 32]),
+            block_height: 0,
+            burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+        })
+    }
+}
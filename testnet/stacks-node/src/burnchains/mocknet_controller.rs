@@ -1,24 +1,420 @@
-use std::collections::VecDeque;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Instant;
 
-use super::super::{Config};
-use super::{BurnchainController, BurnchainTip};
+use rand::{Rng, SeedableRng};
+use rand::distributions::Uniform;
+use rand::rngs::StdRng;
+
+use super::super::{Config, Keychain};
+use super::super::config::{OpOrdering, SortitionMode};
+use super::{BurnchainController, BurnchainControllerError, BurnchainTip};
 use super::super::operations::BurnchainOpSigner;
 
-use stacks::burnchains::{Burnchain, BurnchainBlockHeader, BurnchainHeaderHash, BurnchainBlock, Txid, BurnchainStateTransition};
+use stacks::burnchains::{Address, Burnchain, BurnchainBlockHeader, BurnchainHeaderHash, BurnchainBlock, BurnchainSigner, Txid, BurnchainStateTransition};
 use stacks::burnchains::bitcoin::BitcoinBlock;
 use stacks::chainstate::burn::db::sortdb::{
-    SortitionDB, SortitionHandleTx
+    SortitionDB, SortitionHandleTx, SortitionId
 };
-use stacks::chainstate::burn::{BlockSnapshot};
+use stacks::chainstate::burn::{BlockHeaderHash, BlockSnapshot, SortitionHash, VRFSeed};
+use stacks::chainstate::burn::distribution::BurnSamplePoint;
+use stacks::address::AddressHashMode;
+use stacks::chainstate::stacks::StacksAddress;
 use stacks::chainstate::burn::operations::{
     LeaderBlockCommitOp,
     LeaderKeyRegisterOp,
     UserBurnSupportOp,
+    BlockstackOperation,
     BlockstackOperationType,
 };
+use stacks::util::db::Error as db_error;
 use stacks::util::hash::Sha256Sum;
+use stacks::util::secp256k1::Secp256k1PublicKey;
 use stacks::util::get_epoch_time_secs;
+use stacks::util::uint::Uint256;
+use stacks::util::vrf::VRFPublicKey;
+
+/// Shared view of `MocknetController::is_reorging`.
+#[derive(Debug, Clone)]
+pub struct ReorgWatch(Arc<AtomicBool>);
+
+impl ReorgWatch {
+    pub fn is_reorging(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Holds the reorg flag set, clearing it on drop so a panic mid-reorg can't leave it stuck.
+struct ReorgingGuard(Arc<AtomicBool>);
+
+impl ReorgingGuard {
+    fn new(flag: Arc<AtomicBool>) -> ReorgingGuard {
+        flag.store(true, Ordering::SeqCst);
+        ReorgingGuard(flag)
+    }
+}
+
+impl Drop for ReorgingGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Headers tried when steering a sortition (`set_next_sortition_vrf`, round-robin mode) before giving up.
+const MAX_SORTITION_OVERRIDE_ATTEMPTS: u32 = 4096;
+
+/// The kinds of burnchain operations the mocknet accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpTypeKind {
+    LeaderKeyRegister,
+    LeaderBlockCommit,
+    UserBurnSupport,
+}
+
+impl OpTypeKind {
+    pub fn of(operation: &BlockstackOperationType) -> OpTypeKind {
+        match operation {
+            BlockstackOperationType::LeaderKeyRegister(_) => OpTypeKind::LeaderKeyRegister,
+            BlockstackOperationType::LeaderBlockCommit(_) => OpTypeKind::LeaderBlockCommit,
+            BlockstackOperationType::UserBurnSupport(_) => OpTypeKind::UserBurnSupport,
+        }
+    }
+
+    /// The snake_case name used in config files and exports, e.g. `leader_block_commit`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpTypeKind::LeaderKeyRegister => "leader_key_register",
+            OpTypeKind::LeaderBlockCommit => "leader_block_commit",
+            OpTypeKind::UserBurnSupport => "user_burn_support",
+        }
+    }
+
+    /// Parse the snake_case name used in config files, e.g. `leader_block_commit`.
+    pub fn from_name(name: &str) -> Option<OpTypeKind> {
+        match name {
+            "leader_key_register" => Some(OpTypeKind::LeaderKeyRegister),
+            "leader_block_commit" => Some(OpTypeKind::LeaderBlockCommit),
+            "user_burn_support" => Some(OpTypeKind::UserBurnSupport),
+            _ => None
+        }
+    }
+}
+
+/// The individual checks reported by `explain_validation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationRule {
+    BurnFee,
+    Memo,
+    ZeroSeed,
+    KeyExpiry,
+    ParentPointer,
+    ParentDepth,
+    KeyReference,
+    ConsensusHashFreshness,
+    /// The commit's inputs hash to the address of the leader key it uses
+    KeyAddressMatch,
+    /// The operation is neither queued nor mined within `mocknet.dedup_window_blocks`
+    Duplicate,
+    /// The signer holds the keys the operation names
+    SignerMismatch,
+    /// The signer's signatures meet the threshold of those keys
+    InsufficientSignatures,
+    /// The signer signed, if the operation type is in `mocknet.require_signature_for`
+    MissingSignature,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationCheck {
+    pub rule: ValidationRule,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of every check `explain_validation` ran on an operation, in the order they ran.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValidationReport {
+    pub checks: Vec<ValidationCheck>,
+}
+
+impl ValidationReport {
+    fn record(&mut self, rule: ValidationRule, passed: bool, detail: String) {
+        self.checks.push(ValidationCheck { rule, passed, detail });
+    }
+
+    pub fn failures(&self) -> Vec<ValidationRule> {
+        self.checks.iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.rule)
+            .collect()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    fn first_failure(&self) -> Option<&ValidationCheck> {
+        self.checks.iter().find(|check| !check.passed)
+    }
+}
+
+/// How up to date a chain tip read with `get_chain_tip_with` must be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsistencyLevel {
+    /// The tip this controller last mined or switched to; cheap but may lag behind
+    /// blocks processed by another controller sharing the sortition DB
+    Cached,
+    /// The canonical tip as currently recorded in the sortition DB
+    Fresh,
+}
+
+/// First violation `validate_full_chain` found on the canonical fork.
+#[derive(Debug)]
+pub enum ChainValidationError {
+    /// The block at `block_height` does not link to its parent's header hash
+    HeaderChainBroken { block_height: u64 },
+    /// An operation mined at `block_height` fails `rule`, as `explain_validation` reports it
+    InvalidOperation { block_height: u64, txid: Txid, rule: ValidationRule, detail: String },
+    /// The chain could not be read back
+    ControllerError(BurnchainControllerError),
+}
+
+impl From<BurnchainControllerError> for ChainValidationError {
+    fn from(e: BurnchainControllerError) -> ChainValidationError {
+        ChainValidationError::ControllerError(e)
+    }
+}
+
+/// Why `submit_operation` refused an operation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    /// A registered memo validator refused the operation's memo
+    InvalidMemo,
+    /// A block commit's `new_seed` is all zeros, which usually means it was never set
+    ZeroSeed,
+    /// A block commit uses a leader key older than `mocknet.key_expiry_blocks`
+    ExpiredLeaderKey,
+    /// The signer produced fewer valid signatures than its threshold requires
+    InsufficientSignatures,
+    /// The operation type is in `mocknet.require_signature_for` but the signer produced no signatures
+    MissingSignature,
+    /// The signer does not hold the keys the operation names: a block commit's inputs, or the
+    /// keys behind the address of a leader key registration or user burn
+    SignerMismatch,
+    /// An identical operation is queued, or was mined within `mocknet.dedup_window_blocks`
+    Duplicate,
+    /// A block commit's parent is deeper below the chain tip than `mocknet.max_parent_depth`
+    ParentTooOld,
+    /// A block commit's `key_block_ptr` is above the chain tip, so its leader key cannot exist yet
+    FutureKeyReference,
+}
+
+pub type MemoValidator = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+/// Verdict of the submission policy on a submitted operation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmitDecision {
+    /// Queue the operation, skipping the built-in checks
+    Accept,
+    /// Refuse the operation, as `try_submit_operation` reports it
+    Reject(RejectReason),
+    /// Hold the operation back and consult the policy again before the next block is mined
+    Defer,
+}
+
+/// Admission rule replacing the built-in submission checks.
+pub type SubmissionPolicy = Box<dyn FnMut(&BlockstackOperationType) -> SubmitDecision + Send>;
+
+/// Consensus rule over all the operations of a block; an `Err` keeps the block from being mined.
+pub type BlockValidator = Box<dyn Fn(&[BlockstackOperationType]) -> Result<(), String> + Send>;
+
+/// Called with each canonical block once it is `mocknet.stability_depth` below the chain tip.
+/// A reorg deeper than that reports the replacement blocks at the reorged heights again.
+pub type FinalizationObserver = Box<dyn FnMut(&BlockSnapshot) + Send>;
+
+/// Outcome of an operation that `sync` tried to include in a block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpProcessingRecord {
+    pub block_height: u64,
+    pub txid: Txid,
+    pub vtxindex: u32,
+    pub accepted: bool,
+}
+
+/// A mined block, as stored by `export_state`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedBlock {
+    pub block_height: u64,
+    pub block_hash: BurnchainHeaderHash,
+    pub parent_block_hash: BurnchainHeaderHash,
+    pub timestamp: u64,
+    pub ops: Vec<BlockstackOperationType>,
+}
+
+/// A block mined by `mine_private` and withheld from the canonical chain until `release_private`.
+#[derive(Debug, Clone)]
+pub struct PrivateBlock {
+    pub header: BurnchainBlockHeader,
+    pub ops: Vec<BlockstackOperationType>,
+}
+
+/// The queued operations of each type, in queue order.
+#[derive(Debug, Clone, Default)]
+pub struct QueuedByType {
+    pub leader_key_registers: Vec<LeaderKeyRegisterOp>,
+    pub block_commits: Vec<LeaderBlockCommitOp>,
+    pub user_burns: Vec<UserBurnSupportOp>,
+}
+
+/// Everything `import_state` needs to rebuild a controller.
+/// Blocks are ordered so that parents always come before their children.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MocknetState {
+    pub chain_tip: BurnchainHeaderHash,
+    pub blocks: Vec<ExportedBlock>,
+    pub queued_operations: Vec<BlockstackOperationType>,
+    pub forks_mined: u64,
+}
+
+/// Everything observable about the controller, as delivered to `subscribe` receivers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControllerEvent {
+    BlockMined { block_height: u64, burn_header_hash: BurnchainHeaderHash },
+    /// The chain tip moved to another fork; `orphaned_ops` were mined on the abandoned blocks
+    Reorg { fork_point_height: u64, new_tip: BurnchainHeaderHash, orphaned_ops: Vec<Txid> },
+    OpAccepted { block_height: u64, txid: Txid },
+    OpRejected { block_height: u64, txid: Txid },
+}
+
+/// Receives the operations of every block the mocknet mines, e.g. to forward them to another system.
+pub trait OpSink {
+    /// Called once per block, after it is committed, with the operations accepted into it.
+    fn on_ops_mined(&mut self, height: u64, ops: &[BlockstackOperationType]);
+}
+
+/// Source of the current time, in seconds since the epoch, for time-dependent mocknet behavior.
+pub trait Clock: Send {
+    fn now_secs(&self) -> u64;
+
+    /// The clock as a `MockClock`, if it is one.
+    fn as_mock(&self) -> Option<&MockClock> {
+        None
+    }
+}
+
+/// The wall clock; used unless another clock is installed with `set_clock_source`.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_secs(&self) -> u64 {
+        get_epoch_time_secs()
+    }
+}
+
+/// A clock that only moves when told to.  Clones share the same time, so a test can keep
+/// a handle on the clock it gave to the controller.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    secs: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new(secs: u64) -> MockClock {
+        MockClock { secs: Arc::new(AtomicU64::new(secs)) }
+    }
+
+    pub fn set(&self, secs: u64) {
+        self.secs.store(secs, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.secs.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_secs(&self) -> u64 {
+        self.secs.load(Ordering::SeqCst)
+    }
+
+    fn as_mock(&self) -> Option<&MockClock> {
+        Some(self)
+    }
+}
+
+/// What an operation burns; key registrations burn nothing.
+fn burn_fee_of(operation: &BlockstackOperationType) -> u64 {
+    match operation {
+        BlockstackOperationType::LeaderKeyRegister(_) => 0,
+        BlockstackOperationType::LeaderBlockCommit(op) => op.burn_fee,
+        BlockstackOperationType::UserBurnSupport(op) => op.burn_fee,
+    }
+}
+
+/// Append `records` to the file at `path` as JSON lines.
+fn append_processing_records(path: &str, records: &[OpProcessingRecord]) -> io::Result<()> {
+    let mut lines = String::new();
+    for record in records.iter() {
+        lines.push_str(&serde_json::to_string(record)?);
+        lines.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(lines.as_bytes())?;
+    file.sync_data()
+}
+
+/// Identifies an operation by its content, ignoring where it was mined.
+fn content_id(operation: &BlockstackOperationType) -> Sha256Sum {
+    let mut operation = operation.clone();
+    match operation {
+        BlockstackOperationType::LeaderKeyRegister(ref mut op) => {
+            op.txid = Txid([0u8; 32]);
+            op.vtxindex = 0;
+            op.block_height = 0;
+            op.burn_header_hash = BurnchainHeaderHash([0u8; 32]);
+        },
+        BlockstackOperationType::LeaderBlockCommit(ref mut op) => {
+            op.txid = Txid([0u8; 32]);
+            op.vtxindex = 0;
+            op.block_height = 0;
+            op.burn_header_hash = BurnchainHeaderHash([0u8; 32]);
+        },
+        BlockstackOperationType::UserBurnSupport(ref mut op) => {
+            op.txid = Txid([0u8; 32]);
+            op.vtxindex = 0;
+            op.block_height = 0;
+            op.burn_header_hash = BurnchainHeaderHash([0u8; 32]);
+        },
+    }
+    Sha256Sum::from_data(&serde_json::to_vec(&operation).expect("FATAL: failed to serialize operation"))
+}
+
+/// Whether two blocks hold the same operations, in the same order.
+fn same_operations(a: &[BlockstackOperationType], b: &[BlockstackOperationType]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| content_id(a) == content_id(b))
+}
+
+/// A submitted operation waiting to be mined.
+struct QueuedOperation {
+    operation: BlockstackOperationType,
+    queued_at: u64,
+    /// Height of the chain tip when the operation was queued
+    queued_at_height: u64,
+    content_id: Sha256Sum,
+}
+
+impl QueuedOperation {
+    fn new(operation: BlockstackOperationType, queued_at: u64, queued_at_height: u64) -> QueuedOperation {
+        let content_id = content_id(&operation);
+        QueuedOperation { operation, queued_at, queued_at_height, content_id }
+    }
+}
 
 /// MocknetController is simulating a simplistic burnchain.
 pub struct MocknetController {
@@ -26,7 +422,32 @@ pub struct MocknetController {
     burnchain: Burnchain,
     db: Option<SortitionDB>,
     chain_tip: Option<BurnchainTip>,
-    queued_operations: VecDeque<BlockstackOperationType>,
+    queued_operations: VecDeque<QueuedOperation>,
+    processing_log: VecDeque<OpProcessingRecord>,
+    memo_validators: HashMap<OpTypeKind, MemoValidator>,
+    last_orphaned_ops: Vec<Txid>,
+    forks_mined: u64,
+    op_sinks: Vec<Box<dyn OpSink + Send>>,
+    block_validator: Option<BlockValidator>,
+    block_interval_rng: StdRng,
+    chaos_rng: StdRng,
+    event_subscribers: Vec<Sender<ControllerEvent>>,
+    next_sortition_vrf: Option<[u8; 32]>,
+    private_tip: Option<BurnchainBlockHeader>,
+    private_blocks_mined: u64,
+    clock: Box<dyn Clock>,
+    last_equivocations: Vec<(VRFPublicKey, Vec<Txid>)>,
+    annotations: HashMap<u64, String>,
+    total_ops_mined: u64,
+    round_robin_turn: u64,
+    held_blocks: Option<Vec<PrivateBlock>>,
+    reorging: Arc<AtomicBool>,
+    finalization_observer: Option<FinalizationObserver>,
+    last_finalized_height: Option<u64>,
+    submission_policy: Option<SubmissionPolicy>,
+    deferred_operations: Vec<BlockstackOperationType>,
+    inclusion_latencies: BTreeMap<u64, usize>,
+    scheduled_reorgs_done: HashSet<usize>,
 }
 
 impl MocknetController {
@@ -35,10 +456,17 @@ impl MocknetController {
         Box::new(Self::new(config))
     }
 
-    fn new(config: Config) -> Self {
+    pub fn new(config: Config) -> Self {
         debug!("Opening Burnchain at {}", &config.get_burn_db_path());
         let burnchain = Burnchain::new(&config.get_burn_db_path(), &config.burnchain.chain, &"regtest".to_string())
             .expect("Error while instantiating burnchain");
+        Self::with_burnchain(config, burnchain)
+    }
+
+    /// Use the consensus parameters of `burnchain` instead of the regtest ones.
+    pub fn with_burnchain(config: Config, burnchain: Burnchain) -> Self {
+        let block_interval_rng = StdRng::seed_from_u64(config.mocknet.block_interval_seed);
+        let chaos_rng = StdRng::seed_from_u64(config.mocknet.chaos.seed);
 
         Self {
             config: config,
@@ -46,164 +474,2023 @@ impl MocknetController {
             db: None,
             queued_operations: VecDeque::new(),
             chain_tip: None,
+            processing_log: VecDeque::new(),
+            memo_validators: HashMap::new(),
+            last_orphaned_ops: vec![],
+            forks_mined: 0,
+            op_sinks: vec![],
+            block_validator: None,
+            block_interval_rng,
+            chaos_rng,
+            event_subscribers: vec![],
+            next_sortition_vrf: None,
+            private_tip: None,
+            private_blocks_mined: 0,
+            clock: Box::new(SystemClock),
+            last_equivocations: vec![],
+            annotations: HashMap::new(),
+            total_ops_mined: 0,
+            round_robin_turn: 0,
+            held_blocks: None,
+            reorging: Arc::new(AtomicBool::new(false)),
+            finalization_observer: None,
+            last_finalized_height: None,
+            submission_policy: None,
+            deferred_operations: vec![],
+            inclusion_latencies: BTreeMap::new(),
+            scheduled_reorgs_done: HashSet::new(),
         }
     }
 
-    fn build_next_block_header(current_block: &BlockSnapshot) -> BurnchainBlockHeader {
-        let curr_hash = &current_block.burn_header_hash.to_bytes()[..];
-        let next_hash = Sha256Sum::from_data(&curr_hash);
+    /// The configuration this controller runs with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
-            current_block.block_height + 1,
-            &BurnchainHeaderHash::from_bytes(next_hash.as_bytes()).unwrap(), 
-            &current_block.burn_header_hash, 
-            &vec![],
-            get_epoch_time_secs()));
-        block.header()
+    /// Register a validator for the memo of every submitted operation of type `kind`.
+    /// Replaces any validator previously registered for that type.
+    pub fn set_memo_validator(&mut self, kind: OpTypeKind, validator: MemoValidator) {
+        self.memo_validators.insert(kind, validator);
     }
-}
 
-impl BurnchainController for MocknetController {
-    
-    fn sortdb_ref(&self) -> &SortitionDB {
-        self.db.as_ref().expect("BUG: did not instantiate burn DB")
+    /// Install a validator consulted by `sync` before mining the queued operations.
+    /// When it refuses the block, nothing is mined and the operations stay queued.
+    pub fn set_block_validator(&mut self, validator: BlockValidator) {
+        self.block_validator = Some(validator);
     }
 
-    fn sortdb_mut(&mut self) -> &mut SortitionDB {
-        match self.db {
-            Some(ref mut sortdb) => sortdb,
-            None => {
-                unreachable!();
+    /// Report blocks to `observer` as they become final.  Blocks that are final already
+    /// are not reported.
+    pub fn set_finalization_observer(&mut self, observer: FinalizationObserver) {
+        self.last_finalized_height = match self.chain_tip {
+            Some(ref chain_tip) => chain_tip.block_snapshot.block_height.checked_sub(self.config.mocknet.stability_depth),
+            None => None
+        };
+        self.finalization_observer = Some(observer);
+    }
+
+    /// Hand the canonical blocks that became final since the last call to the finalization observer.
+    fn notify_finalized_blocks(&mut self) -> Result<(), BurnchainControllerError> {
+        if self.finalization_observer.is_none() {
+            return Ok(());
+        }
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        let final_height = match tip_height.checked_sub(self.config.mocknet.stability_depth) {
+            Some(final_height) => final_height,
+            None => return Ok(())
+        };
+        let first_height = match self.last_finalized_height {
+            Some(height) => height + 1,
+            None => self.sortdb_ref().first_block_height
+        };
+        for height in first_height..(final_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            if let Some(ref mut observer) = self.finalization_observer {
+                observer(&snapshot);
             }
+            self.last_finalized_height = Some(height);
         }
+        Ok(())
     }
-    
-    fn get_chain_tip(&mut self) -> BurnchainTip {
-        match &self.chain_tip {
-            Some(chain_tip) => {
-                chain_tip.clone()
-            },
-            None => {
-                unreachable!();
+
+    /// Make the next `sync` pick its sortition winner as if the VRF output were `value`:
+    /// the winner is the commit whose burn range contains `value`, read as a little-endian integer.
+    /// The mocknet achieves this by choosing the block header hash; the override is cleared after one block.
+    pub fn set_next_sortition_vrf(&mut self, value: [u8; 32]) {
+        self.next_sortition_vrf = Some(value);
+    }
+
+    /// Mine blocks until every queued operation is included, giving up after `max_blocks` blocks.
+    /// Blocks the block validator refuses do not count, but it may refuse at most as many.
+    pub fn mine_until_queue_empty(&mut self, max_blocks: u64) -> Result<BurnchainTip, BurnchainControllerError> {
+        let mut chain_tip = self.get_chain_tip();
+        let (mut mined, mut refused) = (0, 0);
+        while mined < max_blocks && refused < max_blocks && !self.queued_operations.is_empty() {
+            let new_tip = self.mine_queued_ops()?;
+            if new_tip.block_snapshot.burn_header_hash == chain_tip.block_snapshot.burn_header_hash {
+                refused += 1;
+            } else {
+                mined += 1;
             }
+            chain_tip = new_tip;
         }
+
+        if !self.queued_operations.is_empty() {
+            return Err(BurnchainControllerError::QueueNotDrained(self.queued_operations.len()));
+        }
+        Ok(chain_tip)
     }
-   
-    fn start(&mut self) -> BurnchainTip {
-        let db = match SortitionDB::connect(&self.config.get_burn_db_file_path(), 0, &BurnchainHeaderHash([0u8; 32]), get_epoch_time_secs(), true) {
-            Ok(db) => db,
-            Err(_) => panic!("Error while connecting to burnchain db")
-        };
-        let block_snapshot = SortitionDB::get_canonical_burn_chain_tip_stubbed(db.conn())
-            .expect("FATAL: failed to get canonical chain tip");
 
-        self.db = Some(db);
+    /// Mine the next block out of the front of the queue.
+    fn mine_queued_ops(&mut self) -> Result<BurnchainTip, BurnchainControllerError> {
+        self.reconsider_deferred_operations();
+        let chain_tip = self.get_chain_tip();
+        self.drop_stale_commits(&chain_tip.block_snapshot);
 
-        let genesis_state = BurnchainTip {
-            block_snapshot,
-            state_transition: BurnchainStateTransition {
-                burn_dist: vec![],
-                accepted_ops: vec![],
-                consumed_leader_keys: vec![]
-            },
-            received_at: Instant::now(),
+        // Simulating mining
+        let mut selected = self.select_block_ops();
+        self.enforce_min_commits(&mut selected);
+        let ops: Vec<_> = self.queued_operations.iter().zip(selected.iter())
+            .filter(|(_, included)| **included)
+            .map(|(queued, _)| queued.operation.clone())
+            .collect();
+        let ops = self.order_block_ops(ops);
+        if let Some(ref validator) = self.block_validator {
+            if let Err(reason) = validator(&ops) {
+                warn!("Block of {} operations refused by the block validator: {}", ops.len(), reason);
+                return Ok(chain_tip);
+            }
+        }
+        let queued_at_heights: HashMap<_, _> = self.queued_operations.iter().zip(selected.iter())
+            .filter(|(_, included)| **included)
+            .map(|(queued, _)| (queued.content_id.clone(), queued.queued_at_height))
+            .collect();
+        let latencies: Vec<u64> = ops.iter()
+            .filter_map(|op| queued_at_heights.get(&content_id(op)))
+            .map(|queued_at_height| chain_tip.block_snapshot.block_height + 1 - queued_at_height)
+            .collect();
+        let remaining: VecDeque<_> = self.queued_operations.drain(..).zip(selected.into_iter())
+            .filter(|(_, included)| !*included)
+            .map(|(queued, _)| queued)
+            .collect();
+        self.queued_operations = remaining;
+
+        let round_robin_winner = match self.config.mocknet.sortition_mode {
+            SortitionMode::RoundRobin => self.next_round_robin_winner(&ops),
+            SortitionMode::BurnWeighted => None
         };
-        self.chain_tip = Some(genesis_state.clone());
+        let new_state = match (self.next_sortition_vrf.take(), round_robin_winner) {
+            (Some(value), _) => self.mine_block_into_range(&chain_tip.block_snapshot, ops, SortitionHash(value).to_uint256())?,
+            (None, Some(winner)) => self.mine_block_won_by(&chain_tip.block_snapshot, ops, winner)?,
+            (None, None) => self.mine_block(&chain_tip.block_snapshot, ops, &[])?
+        };
+
+        // Transmit the new state
+        self.chain_tip = Some(new_state.clone());
+        for latency in latencies.into_iter() {
+            *self.inclusion_latencies.entry(latency).or_insert(0) += 1;
+        }
+        let new_state = self.run_scheduled_reorg(new_state)?;
+        self.notify_finalized_blocks()?;
 
-        genesis_state
+        Ok(new_state)
     }
 
-    fn submit_operation(&mut self, operation: BlockstackOperationType, _op_signer: &mut BurnchainOpSigner) -> bool {
-        self.queued_operations.push_back(operation);
-        true
+    /// Perform the `mocknet.reorg_schedule` entry for the height of `new_tip`, if there is one
+    /// that has not run yet, and return the resulting chain tip.
+    fn run_scheduled_reorg(&mut self, new_tip: BurnchainTip) -> Result<BurnchainTip, BurnchainControllerError> {
+        let height = new_tip.block_snapshot.block_height;
+        let schedule = &self.config.mocknet.reorg_schedule;
+        let done = &self.scheduled_reorgs_done;
+        let index = match (0..schedule.len()).find(|index| schedule[*index].at_height == height && !done.contains(index)) {
+            Some(index) => index,
+            None => return Ok(new_tip)
+        };
+        self.scheduled_reorgs_done.insert(index);
+        let scheduled = self.config.mocknet.reorg_schedule[index].clone();
+        info!("Scheduled reorg at height {}: replacing {} blocks with {}", height, scheduled.depth, scheduled.alt_blocks.len());
+        self.reorg(scheduled.depth, scheduled.alt_blocks)
     }
 
-    fn sync(&mut self) -> BurnchainTip {
-        let chain_tip = self.get_chain_tip();
+    /// The miner whose turn it is to win among those committing in `ops`, in order of their
+    /// first commit.  `None` if no commits are included.
+    fn next_round_robin_winner(&mut self, ops: &[BlockstackOperationType]) -> Option<BurnchainSigner> {
+        let mut miners: Vec<BurnchainSigner> = vec![];
+        for op in ops.iter() {
+            if let BlockstackOperationType::LeaderBlockCommit(op) = op {
+                if !miners.contains(&op.input) {
+                    miners.push(op.input.clone());
+                }
+            }
+        }
+        if miners.is_empty() {
+            return None;
+        }
+        let winner = miners.swap_remove((self.round_robin_turn % miners.len() as u64) as usize);
+        self.round_robin_turn += 1;
+        Some(winner)
+    }
 
-        // Simulating mining
-        let next_block_header = Self::build_next_block_header(&chain_tip.block_snapshot);
-        let mut vtxindex = 1;
-        let mut ops = vec![];
-
-        while let Some(payload) = self.queued_operations.pop_front() {
-            let txid = Txid(Sha256Sum::from_data(format!("{}::{}", next_block_header.block_height, vtxindex).as_bytes()).0);
-            let op = match payload {
-                BlockstackOperationType::LeaderKeyRegister(payload) => {
-                    BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
-                        consensus_hash: payload.consensus_hash,
-                        public_key: payload.public_key,
-                        memo: payload.memo,
-                        address: payload.address,
-                        txid,
-                        vtxindex: vtxindex,
-                        block_height: next_block_header.block_height,
-                        burn_header_hash: next_block_header.block_hash,
-                    })
+    /// Drop the queued block commits that became invalid for a block mined on `parent` while
+    /// they waited in the queue.
+    fn drop_stale_commits(&mut self, parent: &BlockSnapshot) {
+        let stale: Vec<Option<&str>> = self.queued_operations.iter()
+            .map(|queued| match queued.operation {
+                BlockstackOperationType::LeaderBlockCommit(ref op) => {
+                    if self.is_leader_key_expired_after(op.key_block_ptr as u64, parent.block_height) {
+                        Some("its leader key is older than mocknet.key_expiry_blocks")
+                    } else if self.is_parent_too_old_after(op, parent.block_height) {
+                        Some("its parent is older than mocknet.max_parent_depth")
+                    } else {
+                        None
+                    }
                 },
-                BlockstackOperationType::LeaderBlockCommit(payload) => {
-                    BlockstackOperationType::LeaderBlockCommit(LeaderBlockCommitOp {
-                        block_header_hash: payload.block_header_hash,
-                        new_seed: payload.new_seed,
-                        parent_block_ptr: payload.parent_block_ptr,
-                        parent_vtxindex: payload.parent_vtxindex,
-                        key_block_ptr: payload.key_block_ptr,
-                        key_vtxindex: payload.key_vtxindex,
-                        memo: payload.memo,
-                        burn_fee: payload.burn_fee,
-                        input: payload.input,
-                        txid,
-                        vtxindex: vtxindex,
-                        block_height: next_block_header.block_height,
-                        burn_header_hash: next_block_header.block_hash,
-                    })
+                _ => None
+            })
+            .collect();
+        let remaining: VecDeque<_> = self.queued_operations.drain(..).zip(stale.into_iter())
+            .filter_map(|(queued, stale)| match stale {
+                Some(reason) => {
+                    warn!("Dropping queued block commit: {}", reason);
+                    None
                 },
-                BlockstackOperationType::UserBurnSupport(payload) => {
-                    BlockstackOperationType::UserBurnSupport(UserBurnSupportOp {
-                        address: payload.address,
-                        consensus_hash: payload.consensus_hash,
-                        public_key: payload.public_key,
-                        key_block_ptr: payload.key_block_ptr,
-                        key_vtxindex: payload.key_vtxindex,
-                        block_header_hash_160: payload.block_header_hash_160,
-                        burn_fee: payload.burn_fee,
-                        txid,
-                        vtxindex: vtxindex,
-                        block_height: next_block_header.block_height,
-                        burn_header_hash: next_block_header.block_hash,
-                    })
-                }
-            };
-            ops.push(op);
-            vtxindex += 1;
+                None => Some(queued)
+            })
+            .collect();
+        self.queued_operations = remaining;
+    }
+
+    /// Flag the queued operations that go into the next block, honoring `batch_window_secs`,
+    /// `max_ops_per_block` and the chaos inclusion probability.
+    fn select_block_ops(&mut self) -> Vec<bool> {
+        let max_ops = self.config.mocknet.max_ops_per_block.unwrap_or(usize::max_value());
+        let inclusion_probability = self.config.mocknet.chaos.inclusion_probability;
+        let batch_window_secs = self.config.mocknet.batch_window_secs;
+        let now = self.clock.now_secs();
+
+        let mut selected = vec![false; self.queued_operations.len()];
+        let mut block_size = 0;
+        for (included, queued) in selected.iter_mut().zip(self.queued_operations.iter()) {
+            if block_size >= max_ops {
+                break;
+            }
+            if now.saturating_sub(queued.queued_at) < batch_window_secs {
+                continue;
+            }
+            if inclusion_probability >= 1.0 || self.chaos_rng.gen_bool(inclusion_probability.max(0.0)) {
+                *included = true;
+                block_size += 1;
+            }
         }
+        selected
+    }
 
-        // Include txs in a new block   
-        let (block_snapshot, state_transition) = {
-            match self.db {
-                None => {
-                    unreachable!();
-                },
-                Some(ref mut burn_db) => {
-                    let mut burn_tx = SortitionHandleTx::begin(
-                        burn_db, &chain_tip.block_snapshot.sortition_id).unwrap();
-                    let new_chain_tip = burn_tx.process_block_ops(
-                        &self.burnchain, &chain_tip.block_snapshot, &next_block_header, ops).unwrap();
-                    burn_tx.commit().unwrap();
-                    new_chain_tip
+    /// Arrange the operations of the next block according to `mocknet.op_ordering`.
+    fn order_block_ops(&self, mut ops: Vec<BlockstackOperationType>) -> Vec<BlockstackOperationType> {
+        match self.config.mocknet.op_ordering {
+            OpOrdering::Fifo => {},
+            OpOrdering::BurnFee => {
+                let ordering_seed = self.config.mocknet.ordering_seed.to_be_bytes();
+                let tie_break = |op: &BlockstackOperationType| {
+                    let mut preimage = ordering_seed.to_vec();
+                    preimage.extend_from_slice(&serde_json::to_vec(op).expect("FATAL: failed to serialize operation"));
+                    Sha256Sum::from_data(&preimage).0
+                };
+                ops.sort_by_cached_key(|op| (cmp::Reverse(burn_fee_of(op)), tie_break(op)));
+            }
+        }
+        ops
+    }
+
+    /// Leave the block commits flagged by `select_block_ops` in the queue if there are fewer
+    /// than `mocknet.min_commits_for_sortition`.
+    fn enforce_min_commits(&self, selected: &mut [bool]) {
+        let is_commit = |queued: &QueuedOperation| OpTypeKind::of(&queued.operation) == OpTypeKind::LeaderBlockCommit;
+        let num_commits = self.queued_operations.iter().zip(selected.iter())
+            .filter(|(queued, included)| **included && is_commit(queued))
+            .count();
+        if num_commits == 0 || num_commits >= self.config.mocknet.min_commits_for_sortition {
+            return;
+        }
+        warn!("Keeping {} block commits queued: at least {} are needed for a sortition",
+              num_commits, self.config.mocknet.min_commits_for_sortition);
+        for (included, queued) in selected.iter_mut().zip(self.queued_operations.iter()) {
+            if is_commit(queued) {
+                *included = false;
+            }
+        }
+    }
+
+    /// Receive every `ControllerEvent` from now on.
+    pub fn subscribe(&mut self) -> Receiver<ControllerEvent> {
+        let (sender, receiver) = channel();
+        self.event_subscribers.push(sender);
+        receiver
+    }
+
+    fn emit_event(&mut self, event: ControllerEvent) {
+        // Forget about the subscribers that dropped their receiver
+        self.event_subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Register a sink notified of the operations of each mined block.
+    pub fn add_op_sink(&mut self, sink: Box<dyn OpSink + Send>) {
+        self.op_sinks.push(sink);
+    }
+
+    /// Validate and queue an operation, reporting why it was refused.
+    pub fn try_submit_operation(&mut self, operation: BlockstackOperationType, op_signer: &mut BurnchainOpSigner) -> Result<(), RejectReason> {
+        let decision = match self.submission_policy {
+            Some(ref mut policy) => Some(policy(&operation)),
+            None => None
+        };
+        match decision {
+            Some(SubmitDecision::Accept) => {
+                self.enqueue(operation);
+                return Ok(());
+            },
+            Some(SubmitDecision::Reject(reason)) => return Err(reason),
+            Some(SubmitDecision::Defer) => {
+                self.deferred_operations.push(operation);
+                return Ok(());
+            },
+            None => {}
+        }
+
+        self.validate_submission(&operation)?;
+        self.verify_signer(&operation, op_signer)?;
+        self.enqueue(operation);
+        Ok(())
+    }
+
+    fn validate_submission(&self, operation: &BlockstackOperationType) -> Result<(), RejectReason> {
+        let operation_id = content_id(operation);
+        if self.queued_operations.iter().any(|queued| queued.content_id == operation_id) {
+            return Err(RejectReason::Duplicate);
+        }
+        match self.is_recently_mined(&operation_id) {
+            Ok(true) => return Err(RejectReason::Duplicate),
+            Ok(false) => {},
+            Err(e) => warn!("Failed to check recent blocks for duplicates: {:?}", e)
+        }
+
+        if let BlockstackOperationType::LeaderBlockCommit(op) = operation {
+            if self.config.mocknet.reject_zero_seed_commits && op.new_seed == VRFSeed([0u8; 32]) {
+                return Err(RejectReason::ZeroSeed);
+            }
+            if self.is_future_block(op.key_block_ptr as u64) {
+                return Err(RejectReason::FutureKeyReference);
+            }
+            if self.is_leader_key_expired(op.key_block_ptr as u64) {
+                return Err(RejectReason::ExpiredLeaderKey);
+            }
+            if self.is_parent_too_old(op) {
+                return Err(RejectReason::ParentTooOld);
+            }
+        }
+
+        let memo = match operation {
+            BlockstackOperationType::LeaderKeyRegister(op) => Some(&op.memo),
+            BlockstackOperationType::LeaderBlockCommit(op) => Some(&op.memo),
+            BlockstackOperationType::UserBurnSupport(_) => None
+        };
+        if let (Some(memo), Some(validator)) = (memo, self.memo_validators.get(&OpTypeKind::of(operation))) {
+            if !validator(&memo[..]) {
+                return Err(RejectReason::InvalidMemo);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the signer holds the keys the operation names, then have it sign the operation
+    /// and check the signatures against the M-of-N threshold of those keys.  A signer that yields
+    /// no signatures at all (e.g. a disposed one) still has to hold the right keys, but is let
+    /// through unsigned unless the operation type is in `mocknet.require_signature_for`.
+    fn verify_signer(&self, operation: &BlockstackOperationType, op_signer: &mut BurnchainOpSigner) -> Result<(), RejectReason> {
+        let (operation_keys, num_sigs) = match operation {
+            BlockstackOperationType::LeaderBlockCommit(op) => (op.input.public_keys.clone(), op.input.num_sigs),
+            BlockstackOperationType::LeaderKeyRegister(op) => MocknetController::keys_behind_address(op_signer, &op.address)?,
+            BlockstackOperationType::UserBurnSupport(op) => MocknetController::keys_behind_address(op_signer, &op.address)?
+        };
+        let authorized_keys: Vec<_> = operation_keys.iter()
+            .map(|public_key| public_key.to_bytes_compressed())
+            .collect();
+        let signer_keys: Vec<_> = op_signer.get_public_keys().iter()
+            .map(|public_key| public_key.to_bytes_compressed())
+            .collect();
+        if signer_keys != authorized_keys || op_signer.num_sigs_required() != num_sigs {
+            return Err(RejectReason::SignerMismatch);
+        }
+
+        let serialized = serde_json::to_vec(operation)
+            .expect("FATAL: failed to serialize operation");
+        let digest = Sha256Sum::from_data(&serialized);
+
+        let signatures = op_signer.sign_message_multisig(digest.as_bytes());
+        if signatures.is_empty() {
+            if self.config.mocknet.require_signature_for.contains(&OpTypeKind::of(operation)) {
+                return Err(RejectReason::MissingSignature);
+            }
+            return Ok(())
+        }
+
+        let mut signed_by = vec![];
+        for signature in signatures.iter() {
+            if let Ok(public_key) = Secp256k1PublicKey::recover_to_pubkey(digest.as_bytes(), signature) {
+                let public_key = public_key.to_bytes_compressed();
+                if authorized_keys.contains(&public_key) && !signed_by.contains(&public_key) {
+                    signed_by.push(public_key);
                 }
             }
+        }
+
+        if signed_by.len() < num_sigs {
+            return Err(RejectReason::InsufficientSignatures);
+        }
+        Ok(())
+    }
+
+    /// The signer's public keys and threshold, if they hash to `address`.
+    fn keys_behind_address(op_signer: &BurnchainOpSigner, address: &StacksAddress) -> Result<(Vec<Secp256k1PublicKey>, usize), RejectReason> {
+        let public_keys = op_signer.get_public_keys().to_vec();
+        let num_sigs = op_signer.num_sigs_required();
+        let hash_modes = [AddressHashMode::SerializeP2PKH, AddressHashMode::SerializeP2SH,
+                          AddressHashMode::SerializeP2WPKH, AddressHashMode::SerializeP2WSH];
+        let controls_address = hash_modes.iter()
+            .any(|hash_mode| StacksAddress::from_public_keys(address.version, hash_mode, num_sigs, &public_keys).as_ref() == Some(address));
+        if !controls_address {
+            return Err(RejectReason::SignerMismatch);
+        }
+        Ok((public_keys, num_sigs))
+    }
+
+    /// Whether an operation with content id `operation_id` was mined in the last
+    /// `mocknet.dedup_window_blocks` blocks.
+    fn is_recently_mined(&self, operation_id: &Sha256Sum) -> Result<bool, BurnchainControllerError> {
+        let chain_tip = match self.chain_tip {
+            Some(ref chain_tip) => &chain_tip.block_snapshot,
+            None => return Ok(false)
         };
+        let window_start = cmp::max(
+            (chain_tip.block_height + 1).saturating_sub(self.config.mocknet.dedup_window_blocks),
+            self.sortdb_ref().first_block_height + 1);
+        for height in window_start..(chain_tip.block_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            if self.get_block_ops(&snapshot)?.iter().any(|op| &content_id(op) == operation_id) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
-        // Transmit the new state
-        let new_state = BurnchainTip {
-            block_snapshot,
-            state_transition,
-            received_at: Instant::now()
+    /// Whether a key registered at `key_block_height` is too old to be used in the next block.
+    fn is_leader_key_expired(&self, key_block_height: u64) -> bool {
+        match self.chain_tip {
+            Some(ref chain_tip) => self.is_leader_key_expired_after(key_block_height, chain_tip.block_snapshot.block_height),
+            None => false
+        }
+    }
+
+    /// Whether a key registered at `key_block_height` is too old to be used in the block
+    /// following `parent_height`.
+    fn is_leader_key_expired_after(&self, key_block_height: u64, parent_height: u64) -> bool {
+        let key_expiry_blocks = match self.config.mocknet.key_expiry_blocks {
+            Some(key_expiry_blocks) => key_expiry_blocks,
+            None => return false
         };
-        self.chain_tip = Some(new_state.clone());
+        (parent_height + 1).saturating_sub(key_block_height) > key_expiry_blocks
+    }
 
-        new_state
+    /// Whether `block_height` is above the chain tip.
+    fn is_future_block(&self, block_height: u64) -> bool {
+        match self.chain_tip {
+            Some(ref chain_tip) => block_height > chain_tip.block_snapshot.block_height,
+            None => false
+        }
+    }
+
+    /// Whether `op` builds on a block more than `max_parent_depth` blocks below the chain tip.
+    /// Commits building on genesis are never too old.
+    fn is_parent_too_old(&self, op: &LeaderBlockCommitOp) -> bool {
+        match self.chain_tip {
+            Some(ref chain_tip) => self.is_parent_too_old_after(op, chain_tip.block_snapshot.block_height),
+            None => false
+        }
+    }
+
+    /// Whether `op` builds on a block more than `max_parent_depth` blocks below `parent_height`.
+    fn is_parent_too_old_after(&self, op: &LeaderBlockCommitOp, parent_height: u64) -> bool {
+        let max_parent_depth = match self.config.mocknet.max_parent_depth {
+            Some(max_parent_depth) => max_parent_depth,
+            None => return false
+        };
+        if op.parent_block_ptr == 0 && op.parent_vtxindex == 0 {
+            return false;
+        }
+        parent_height.saturating_sub(op.parent_block_ptr as u64) > max_parent_depth
+    }
+
+    /// Decide on submissions with `policy` instead of the built-in checks.
+    pub fn set_submission_policy(&mut self, policy: SubmissionPolicy) {
+        self.submission_policy = Some(policy);
+    }
+
+    /// Operations the submission policy deferred, in submission order.
+    pub fn deferred_operations(&self) -> &[BlockstackOperationType] {
+        &self.deferred_operations
+    }
+
+    /// Consult the submission policy again on the deferred operations.
+    fn reconsider_deferred_operations(&mut self) {
+        let deferred_operations = mem::replace(&mut self.deferred_operations, vec![]);
+        for operation in deferred_operations.into_iter() {
+            let decision = match self.submission_policy {
+                Some(ref mut policy) => policy(&operation),
+                None => SubmitDecision::Accept
+            };
+            match decision {
+                SubmitDecision::Accept => self.enqueue(operation),
+                SubmitDecision::Reject(reason) => warn!("Dropping deferred operation {}: {:?}", operation.txid(), reason),
+                SubmitDecision::Defer => self.deferred_operations.push(operation)
+            }
+        }
+    }
+
+    /// Most recent processing records, oldest first.
+    /// Holds at most `mocknet.processing_log_capacity` entries.
+    pub fn processing_log(&self) -> &VecDeque<OpProcessingRecord> {
+        &self.processing_log
+    }
+
+    /// Drain the operations that were submitted but not mined yet.
+    pub fn take_queue(&mut self) -> VecDeque<BlockstackOperationType> {
+        self.queued_operations.drain(..).map(|queued| queued.operation).collect()
+    }
+
+    /// Copy of the operations that were submitted but not mined yet, split by type.
+    pub fn queued_by_type(&self) -> QueuedByType {
+        let mut queued_by_type = QueuedByType::default();
+        for queued in self.queued_operations.iter() {
+            match queued.operation {
+                BlockstackOperationType::LeaderKeyRegister(ref op) => queued_by_type.leader_key_registers.push(op.clone()),
+                BlockstackOperationType::LeaderBlockCommit(ref op) => queued_by_type.block_commits.push(op.clone()),
+                BlockstackOperationType::UserBurnSupport(ref op) => queued_by_type.user_burns.push(op.clone()),
+            }
+        }
+        queued_by_type
+    }
+
+    fn enqueue(&mut self, operation: BlockstackOperationType) {
+        let queued_at = self.clock.now_secs();
+        let queued_at_height = self.chain_tip.as_ref().map(|tip| tip.block_snapshot.block_height).unwrap_or(0);
+        self.queued_operations.push_back(QueuedOperation::new(operation, queued_at, queued_at_height));
+    }
+
+    /// Use `clock` instead of the wall clock from now on.
+    pub fn set_clock_source(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn mock_clock(&self) -> Result<&MockClock, BurnchainControllerError> {
+        self.clock.as_mock()
+            .ok_or_else(|| BurnchainControllerError::InvalidState("the controller does not use a MockClock".to_string()))
+    }
+
+    /// Move the installed `MockClock` forward by `secs`.
+    pub fn advance_clock(&mut self, secs: u64) -> Result<(), BurnchainControllerError> {
+        self.mock_clock()?.advance(secs);
+        Ok(())
+    }
+
+    /// Set the installed `MockClock` to `secs` since the epoch.
+    pub fn set_clock(&mut self, secs: u64) -> Result<(), BurnchainControllerError> {
+        self.mock_clock()?.set(secs);
+        Ok(())
+    }
+
+    /// Install a queue taken from another controller.
+    /// Operations that would not be valid in the block after this controller's tip are dropped.
+    pub fn seed_queue(&mut self, ops: VecDeque<BlockstackOperationType>) {
+        for op in ops.into_iter() {
+            if self.check_against_tip(&op) {
+                self.enqueue(op);
+            } else {
+                warn!("Dropping seeded operation {}: invalid against the current chain tip", op.txid());
+            }
+        }
+    }
+
+    /// Run the consensus checks for `operation` as if it were mined in the block following the tip.
+    fn check_against_tip(&self, operation: &BlockstackOperationType) -> bool {
+        let chain_tip = match self.chain_tip {
+            Some(ref chain_tip) => chain_tip,
+            None => return false
+        };
+        let next_block_height = chain_tip.block_snapshot.block_height + 1;
+        let ic = self.sortdb_ref().index_handle(&chain_tip.block_snapshot.sortition_id);
+
+        let result = match operation {
+            BlockstackOperationType::LeaderKeyRegister(op) => {
+                let mut op = op.clone();
+                op.block_height = next_block_height;
+                op.check(&self.burnchain, &ic)
+            },
+            BlockstackOperationType::LeaderBlockCommit(op) => {
+                let mut op = op.clone();
+                op.block_height = next_block_height;
+                op.check(&self.burnchain, &ic)
+            },
+            BlockstackOperationType::UserBurnSupport(op) => {
+                let mut op = op.clone();
+                op.block_height = next_block_height;
+                op.check(&self.burnchain, &ic)
+            }
+        };
+        result.is_ok()
+    }
+
+    /// Run each check that applies to `operation` as if it were submitted now and mined in the
+    /// block following the tip, and report how every one of them went.  The key address check
+    /// needs the leader key the commit references, so it only runs if that key was found.
+    /// Checks on the signer are left to `explain_submission`.
+    pub fn explain_validation(&self, operation: &BlockstackOperationType) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let operation_id = content_id(operation);
+        let queued = self.queued_operations.iter().any(|queued| queued.content_id == operation_id);
+        match self.is_recently_mined(&operation_id) {
+            Ok(mined) => {
+                let detail = if queued {
+                    "already queued".to_string()
+                } else if mined {
+                    format!("mined within the last {} blocks", self.config.mocknet.dedup_window_blocks)
+                } else {
+                    "not queued or recently mined".to_string()
+                };
+                report.record(ValidationRule::Duplicate, !queued && !mined, detail)
+            },
+            Err(e) => report.record(ValidationRule::Duplicate, false, format!("failed to look up recent blocks: {:?}", e))
+        }
+        report.checks.extend(self.explain_validation_after(operation, &self.get_chain_tip_ref().block_snapshot).checks);
+        report
+    }
+
+    /// `explain_validation`, followed by the checks `submit_operation` runs on `op_signer`.
+    /// The signer is asked to sign a copy of itself, so explaining doesn't use it up.
+    pub fn explain_submission(&self, operation: &BlockstackOperationType, op_signer: &BurnchainOpSigner) -> ValidationReport {
+        let mut report = self.explain_validation(operation);
+        match self.verify_signer(operation, &mut op_signer.clone()) {
+            Err(RejectReason::SignerMismatch) => {
+                report.record(ValidationRule::SignerMismatch, false, "signer does not hold the operation's keys".to_string());
+            },
+            result => {
+                report.record(ValidationRule::SignerMismatch, true, format!("signer holds {} keys", op_signer.get_public_keys().len()));
+                report.record(ValidationRule::InsufficientSignatures, result != Err(RejectReason::InsufficientSignatures),
+                              format!("{} signatures required", op_signer.num_sigs_required()));
+                report.record(ValidationRule::MissingSignature, result != Err(RejectReason::MissingSignature),
+                              format!("signatures {}required", if self.config.mocknet.require_signature_for.contains(&OpTypeKind::of(operation)) { "" } else { "not " }));
+            }
+        }
+        report
+    }
+
+    /// `explain_validation` as if `operation` were mined in the block following `parent`.
+    fn explain_validation_after(&self, operation: &BlockstackOperationType, parent: &BlockSnapshot) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let next_block_height = parent.block_height + 1;
+        let ic = self.sortdb_ref().index_handle(&parent.sortition_id);
+
+        let memo = match operation {
+            BlockstackOperationType::LeaderKeyRegister(op) => Some(&op.memo),
+            BlockstackOperationType::LeaderBlockCommit(op) => Some(&op.memo),
+            BlockstackOperationType::UserBurnSupport(_) => None
+        };
+        if let (Some(memo), Some(validator)) = (memo, self.memo_validators.get(&OpTypeKind::of(operation))) {
+            report.record(ValidationRule::Memo, validator(&memo[..]), format!("{}-byte memo checked by the registered validator", memo.len()));
+        }
+
+        let consensus_hash = match operation {
+            BlockstackOperationType::LeaderKeyRegister(op) => Some(&op.consensus_hash),
+            BlockstackOperationType::LeaderBlockCommit(_) => None,
+            BlockstackOperationType::UserBurnSupport(op) => Some(&op.consensus_hash)
+        };
+        if let Some(consensus_hash) = consensus_hash {
+            let lifetime = self.burnchain.consensus_hash_lifetime as u64;
+            match ic.is_fresh_consensus_hash(lifetime, consensus_hash) {
+                Ok(fresh) => report.record(ValidationRule::ConsensusHashFreshness, fresh,
+                                           format!("consensus hash {} within the last {} blocks", consensus_hash, lifetime)),
+                Err(e) => report.record(ValidationRule::ConsensusHashFreshness, false, format!("failed to look up consensus hash: {:?}", e))
+            }
+        }
+
+        let burn_fee = match operation {
+            BlockstackOperationType::LeaderKeyRegister(_) => None,
+            BlockstackOperationType::LeaderBlockCommit(op) => Some(op.burn_fee),
+            BlockstackOperationType::UserBurnSupport(op) => Some(op.burn_fee)
+        };
+        if let Some(burn_fee) = burn_fee {
+            report.record(ValidationRule::BurnFee, burn_fee > 0, format!("burns {}", burn_fee));
+        }
+
+        let (key_block_ptr, key_vtxindex) = match operation {
+            BlockstackOperationType::LeaderKeyRegister(_) => return report,
+            BlockstackOperationType::LeaderBlockCommit(op) => (op.key_block_ptr as u64, op.key_vtxindex as u32),
+            BlockstackOperationType::UserBurnSupport(op) => (op.key_block_ptr as u64, op.key_vtxindex as u32)
+        };
+        let leader_key = if key_block_ptr >= next_block_height {
+            report.record(ValidationRule::KeyReference, false, format!("leader key at {} is not before block {}", key_block_ptr, next_block_height));
+            None
+        } else {
+            match ic.get_leader_key_at(key_block_ptr, key_vtxindex) {
+                Ok(Some(key)) => Some(key),
+                Ok(None) => {
+                    report.record(ValidationRule::KeyReference, false, format!("no leader key at ({},{})", key_block_ptr, key_vtxindex));
+                    None
+                },
+                Err(e) => {
+                    report.record(ValidationRule::KeyReference, false, format!("failed to look up leader key: {:?}", e));
+                    None
+                }
+            }
+        };
+
+        let op = match operation {
+            BlockstackOperationType::LeaderBlockCommit(op) => op,
+            BlockstackOperationType::UserBurnSupport(op) => {
+                if let Some(key) = leader_key {
+                    report.record(ValidationRule::KeyReference, key.public_key == op.public_key,
+                                  format!("leader key at ({},{}) is {}", key_block_ptr, key_vtxindex, key.public_key.to_hex()));
+                }
+                return report;
+            },
+            BlockstackOperationType::LeaderKeyRegister(_) => unreachable!()
+        };
+
+        if let Some(key) = leader_key {
+            match ic.is_leader_key_consumed(&key) {
+                Ok(consumed) => report.record(ValidationRule::KeyReference, !consumed,
+                                              format!("leader key at ({},{}) {}", key_block_ptr, key_vtxindex, if consumed { "is already used" } else { "is unused" })),
+                Err(e) => report.record(ValidationRule::KeyReference, false, format!("failed to look up leader key status: {:?}", e))
+            }
+            report.record(ValidationRule::KeyAddressMatch, op.input.to_address_bits() == key.address.to_bytes(),
+                          format!("commit inputs against leader key address {}", key.address));
+        }
+
+        if self.config.mocknet.reject_zero_seed_commits {
+            report.record(ValidationRule::ZeroSeed, op.new_seed != VRFSeed([0u8; 32]), format!("new seed {}", op.new_seed.to_hex()));
+        }
+        if self.config.mocknet.key_expiry_blocks.is_some() {
+            report.record(ValidationRule::KeyExpiry, !self.is_leader_key_expired_after(key_block_ptr, parent.block_height), format!("leader key registered at {}", key_block_ptr));
+        }
+
+        let parent_block_height = op.parent_block_ptr as u64;
+        if self.config.mocknet.max_parent_depth.is_some() {
+            report.record(ValidationRule::ParentDepth, !self.is_parent_too_old_after(op, parent.block_height),
+                          format!("parent at {} below tip {}", parent_block_height, parent.block_height));
+        }
+        if op.parent_block_ptr == 0 && op.parent_vtxindex == 0 {
+            report.record(ValidationRule::ParentPointer, true, "builds on the genesis block".to_string());
+        } else if parent_block_height >= next_block_height {
+            report.record(ValidationRule::ParentPointer, false, format!("parent at {} is not before block {}", parent_block_height, next_block_height));
+        } else {
+            match ic.get_block_commit_parent(parent_block_height, op.parent_vtxindex as u32) {
+                Ok(parent) => report.record(ValidationRule::ParentPointer, parent.is_some(),
+                                            format!("parent commit at ({},{})", parent_block_height, op.parent_vtxindex)),
+                Err(e) => report.record(ValidationRule::ParentPointer, false, format!("failed to look up parent commit: {:?}", e))
+            }
+        }
+
+        report
+    }
+
+    fn record_processed_ops(&mut self, ops: &[BlockstackOperationType], state_transition: &BurnchainStateTransition) {
+        let records: Vec<_> = ops.iter()
+            .map(|op| {
+                let txid = op.txid();
+                let accepted = state_transition.accepted_ops.iter()
+                    .any(|accepted_op| accepted_op.txid() == txid);
+                OpProcessingRecord {
+                    block_height: op.block_height(),
+                    txid,
+                    vtxindex: op.vtxindex(),
+                    accepted,
+                }
+            })
+            .collect();
+
+        if let Some(ref path) = self.config.mocknet.processing_log_path {
+            if let Err(e) = append_processing_records(path, &records) {
+                warn!("Failed to append processing records to {}: {}", path, e);
+            }
+        }
+
+        let capacity = self.config.mocknet.processing_log_capacity;
+        if capacity == 0 {
+            return;
+        }
+        self.processing_log.extend(records.into_iter());
+        while self.processing_log.len() > capacity {
+            self.processing_log.pop_front();
+        }
+    }
+
+    /// Roll back the last `depth` blocks of the canonical fork and replace them with `alt_blocks`,
+    /// one block per entry.  The replacement branch must be longer than the one it replaces.
+    /// Operations from the dropped blocks are re-queued if still valid on the new fork.
+    pub fn reorg(&mut self, depth: u64, alt_blocks: Vec<Vec<BlockstackOperationType>>) -> Result<BurnchainTip, BurnchainControllerError> {
+        self.while_reorging(|controller| controller.reorg_canonical_fork(depth, alt_blocks))
+    }
+
+    fn reorg_canonical_fork(&mut self, depth: u64, alt_blocks: Vec<Vec<BlockstackOperationType>>) -> Result<BurnchainTip, BurnchainControllerError> {
+        let chain_tip = self.get_chain_tip();
+        let tip_height = chain_tip.block_snapshot.block_height;
+
+        if depth > tip_height - self.sortdb_ref().first_block_height {
+            return Err(BurnchainControllerError::InvalidReorg(format!("cannot reorg {} blocks from height {}", depth, tip_height)));
+        }
+        if (alt_blocks.len() as u64) <= depth {
+            return Err(BurnchainControllerError::InvalidReorg(format!("{} replacement blocks cannot outweigh {} blocks", alt_blocks.len(), depth)));
+        }
+
+        let fork_point = self.canonical_snapshot_at(tip_height - depth)?;
+
+        self.forks_mined += 1;
+        let salt = self.forks_mined.to_be_bytes();
+        let mut parent = fork_point;
+        let mut new_tip = None;
+        for ops in alt_blocks.into_iter() {
+            let tip = self.mine_block(&parent, ops, &salt)?;
+            parent = tip.block_snapshot.clone();
+            new_tip = Some(tip);
+        }
+        let new_tip = new_tip.expect("BUG: reorg mined no blocks");
+        self.switch_to_fork(new_tip.clone())?;
+
+        Ok(new_tip)
+    }
+
+    /// Import the blocks of `other`'s canonical fork that this controller does not have yet,
+    /// then move to the canonical tip of the combined block tree.
+    /// Operations orphaned by the move are re-queued, as with `reorg`.
+    /// Header hashes do not depend on the operations of a block, so controllers sharing a
+    /// `mocknet.chain_seed` can mine different blocks under the same hash; healing them fails
+    /// with `InvalidState` rather than taking such blocks for the same.
+    pub fn heal_from(&mut self, other: &MocknetController) -> Result<BurnchainTip, BurnchainControllerError> {
+        self.while_reorging(|controller| controller.import_canonical_fork(other))
+    }
+
+    fn import_canonical_fork(&mut self, other: &MocknetController) -> Result<BurnchainTip, BurnchainControllerError> {
+        let other_tip = other.get_chain_tip_ref().block_snapshot.clone();
+        let first_block_height = self.sortdb_ref().first_block_height;
+
+        let mut imported_tips = HashMap::new();
+        for height in (first_block_height + 1)..(other_tip.block_height + 1) {
+            let snapshot = other.canonical_snapshot_at(height)?;
+            if let Some(local) = self.get_snapshot_by_hash(&snapshot.burn_header_hash)? {
+                if !same_operations(&self.get_block_ops(&local)?, &other.get_block_ops(&snapshot)?) {
+                    return Err(BurnchainControllerError::InvalidState(
+                        format!("both controllers mined block {} at height {}, with different operations", snapshot.burn_header_hash, height)));
+                }
+                continue;
+            }
+            let parent = self.get_snapshot_by_hash(&snapshot.parent_burn_header_hash)?
+                .ok_or(BurnchainControllerError::DBError(db_error::NotFoundError))?;
+            let block_header = BurnchainBlockHeader {
+                block_height: snapshot.block_height,
+                block_hash: snapshot.burn_header_hash,
+                parent_block_hash: snapshot.parent_burn_header_hash,
+                num_txs: 0,
+                timestamp: snapshot.burn_header_timestamp,
+            };
+            let ops = other.get_block_ops(&snapshot)?;
+            let tip = self.append_block(&parent, &block_header, ops)?;
+            imported_tips.insert(snapshot.burn_header_hash, tip);
+        }
+
+        let canonical = SortitionDB::get_canonical_burn_chain_tip_stubbed(self.sortdb_ref().conn())?;
+        let chain_tip = self.get_chain_tip();
+        if canonical.burn_header_hash == chain_tip.block_snapshot.burn_header_hash {
+            return Ok(chain_tip);
+        }
+
+        let new_tip = match imported_tips.remove(&canonical.burn_header_hash) {
+            Some(tip) => tip,
+            None => self.tip_from_snapshot(canonical)?
+        };
+        self.switch_to_fork(new_tip.clone())?;
+        Ok(new_tip)
+    }
+
+    /// Mine a block of `payloads` without applying it, extending the last withheld block
+    /// or, if there is none, the canonical chain tip.
+    pub fn mine_private(&mut self, payloads: Vec<BlockstackOperationType>) -> PrivateBlock {
+        let (parent_height, parent_hash, parent_timestamp) = match self.private_tip {
+            Some(ref header) => (header.block_height, header.block_hash.clone(), header.timestamp),
+            None => {
+                let parent = &self.get_chain_tip_ref().block_snapshot;
+                (parent.block_height, parent.burn_header_hash.clone(), parent.burn_header_timestamp)
+            }
+        };
+
+        self.private_blocks_mined += 1;
+        let mut salt = b"private".to_vec();
+        salt.extend_from_slice(&self.private_blocks_mined.to_be_bytes());
+        let timestamp = self.next_block_timestamp(parent_timestamp);
+        let header = self.build_next_block_header(parent_height, &parent_hash, &salt, timestamp);
+
+        let ops = MocknetController::stamp_operations(payloads, &header);
+        self.private_tip = Some(header.clone());
+        PrivateBlock { header, ops }
+    }
+
+    /// Apply withheld blocks, parents first, and switch to the resulting branch if it is
+    /// now the longest one.
+    pub fn release_private(&mut self, blocks: Vec<PrivateBlock>) -> Result<BurnchainTip, BurnchainControllerError> {
+        self.while_reorging(|controller| controller.append_private_blocks(blocks))
+    }
+
+    fn append_private_blocks(&mut self, blocks: Vec<PrivateBlock>) -> Result<BurnchainTip, BurnchainControllerError> {
+        let mut released_tip = None;
+        for block in blocks.into_iter() {
+            let parent = self.get_snapshot_by_hash(&block.header.parent_block_hash)?
+                .ok_or_else(|| BurnchainControllerError::InvalidReorg(format!("parent of private block {} is unknown", block.header.block_hash)))?;
+            if self.private_tip.as_ref() == Some(&block.header) {
+                self.private_tip = None;
+            }
+            released_tip = Some(self.append_block(&parent, &block.header, block.ops)?);
+        }
+
+        let canonical = SortitionDB::get_canonical_burn_chain_tip_stubbed(self.sortdb_ref().conn())?;
+        let chain_tip = self.get_chain_tip();
+        if canonical.burn_header_hash == chain_tip.block_snapshot.burn_header_hash {
+            return Ok(chain_tip);
+        }
+
+        let new_tip = match released_tip {
+            Some(tip) if tip.block_snapshot.burn_header_hash == canonical.burn_header_hash => tip,
+            _ => self.tip_from_snapshot(canonical)?
+        };
+        self.switch_to_fork(new_tip.clone())?;
+        Ok(new_tip)
+    }
+
+    /// Freeze the chain tip: blocks mined with `mine_held` are buffered, like private blocks,
+    /// until `catch_up` reveals them.
+    pub fn hold_tip(&mut self) {
+        if self.held_blocks.is_none() {
+            self.held_blocks = Some(vec![]);
+        }
+    }
+
+    /// Mine a block on top of the held blocks without moving the chain tip.
+    pub fn mine_held(&mut self, payloads: Vec<BlockstackOperationType>) -> Result<(), BurnchainControllerError> {
+        if self.held_blocks.is_none() {
+            return Err(BurnchainControllerError::InvalidState("the chain tip is not held".to_string()));
+        }
+        let block = self.mine_private(payloads);
+        if let Some(ref mut held_blocks) = self.held_blocks {
+            held_blocks.push(block);
+        }
+        Ok(())
+    }
+
+    /// Stop holding the chain tip and release the held blocks one at a time, returning the
+    /// chain tip after each of them.
+    pub fn catch_up(&mut self) -> Result<Vec<BurnchainTip>, BurnchainControllerError> {
+        let held_blocks = self.held_blocks.take().unwrap_or_default();
+        let mut tips = vec![];
+        for block in held_blocks.into_iter() {
+            tips.push(self.release_private(vec![block])?);
+        }
+        Ok(tips)
+    }
+
+    /// Run `f` with `is_reorging` set.
+    fn while_reorging<F>(&mut self, f: F) -> Result<BurnchainTip, BurnchainControllerError>
+    where F: FnOnce(&mut MocknetController) -> Result<BurnchainTip, BurnchainControllerError> {
+        let _guard = ReorgingGuard::new(self.reorging.clone());
+        f(self)
+    }
+
+    /// Whether a `reorg`, `heal_from` or `release_private` is in progress, during which the
+    /// chain tip and the sortition DB may not agree.
+    pub fn is_reorging(&self) -> bool {
+        self.reorging.load(Ordering::SeqCst)
+    }
+
+    /// Handle reporting `is_reorging` to observers that cannot borrow the controller.
+    pub fn reorg_watch(&self) -> ReorgWatch {
+        ReorgWatch(self.reorging.clone())
+    }
+
+    /// Make `new_tip` the chain tip, re-queueing the still-valid operations of the blocks
+    /// that are no longer on the canonical fork.
+    fn switch_to_fork(&mut self, new_tip: BurnchainTip) -> Result<(), BurnchainControllerError> {
+        let old_tip = self.get_chain_tip_ref().block_snapshot.clone();
+        let fork_point = self.find_fork_point(&old_tip, &new_tip.block_snapshot)?;
+
+        let mut orphaned_ops = vec![];
+        for height in (fork_point.block_height + 1)..(old_tip.block_height + 1) {
+            let orphaned_block = self.canonical_snapshot_at(height)?;
+            orphaned_ops.append(&mut self.get_block_ops(&orphaned_block)?);
+        }
+
+        let new_tip_hash = new_tip.block_snapshot.burn_header_hash;
+        self.chain_tip = Some(new_tip);
+        self.annotations.retain(|height, _| *height <= fork_point.block_height);
+        if self.last_finalized_height.map_or(false, |height| height > fork_point.block_height) {
+            self.last_finalized_height = Some(fork_point.block_height);
+        }
+
+        self.last_orphaned_ops = orphaned_ops.iter().map(|op| op.txid()).collect();
+        if fork_point.block_height < old_tip.block_height {
+            self.emit_event(ControllerEvent::Reorg {
+                fork_point_height: fork_point.block_height,
+                new_tip: new_tip_hash,
+                orphaned_ops: self.last_orphaned_ops.clone(),
+            });
+        }
+        for op in orphaned_ops.into_iter().rev() {
+            if self.check_against_tip(&op) {
+                let op = self.bump_requeued_fee(op);
+                let queued_at = self.clock.now_secs();
+                let queued_at_height = self.get_chain_tip_ref().block_snapshot.block_height;
+                self.queued_operations.push_front(QueuedOperation::new(op, queued_at, queued_at_height));
+            } else {
+                debug!("Orphaned operation {} is no longer valid and will not be re-queued", op.txid());
+            }
+        }
+        self.notify_finalized_blocks()
+    }
+
+    /// Raise the burn fee of a re-queued block commit by `requeue_fee_bump_pct`.
+    fn bump_requeued_fee(&self, mut operation: BlockstackOperationType) -> BlockstackOperationType {
+        let bump_pct = self.config.mocknet.requeue_fee_bump_pct;
+        if let BlockstackOperationType::LeaderBlockCommit(ref mut op) = operation {
+            let bump = (op.burn_fee as u128 * bump_pct as u128 / 100) as u64;
+            op.burn_fee = op.burn_fee.saturating_add(bump);
+        }
+        operation
+    }
+
+    /// Highest snapshot that is an ancestor of both `a` and `b`.
+    fn find_fork_point(&self, a: &BlockSnapshot, b: &BlockSnapshot) -> Result<BlockSnapshot, BurnchainControllerError> {
+        self.common_ancestor(a, b)?
+            .ok_or(BurnchainControllerError::DBError(db_error::NotFoundError))
+    }
+
+    /// Most recent block that both `a` and `b` are or descend from, found by walking both
+    /// branches back in step.  `None` if they share no history.
+    pub fn common_ancestor(&self, a: &BlockSnapshot, b: &BlockSnapshot) -> Result<Option<BlockSnapshot>, BurnchainControllerError> {
+        let ic = self.sortdb_ref().index_conn();
+        let mut height = cmp::min(a.block_height, b.block_height);
+        loop {
+            let ancestor_a = match SortitionDB::get_ancestor_snapshot(&ic, height, &a.sortition_id)? {
+                Some(ancestor) => ancestor,
+                None => return Ok(None)
+            };
+            let ancestor_b = match SortitionDB::get_ancestor_snapshot(&ic, height, &b.sortition_id)? {
+                Some(ancestor) => ancestor,
+                None => return Ok(None)
+            };
+            if ancestor_a.burn_header_hash == ancestor_b.burn_header_hash {
+                return Ok(Some(ancestor_a));
+            }
+            if height <= self.sortdb_ref().first_block_height {
+                return Ok(None);
+            }
+            height -= 1;
+        }
+    }
+
+    /// Operations accepted into any block this controller appended, on any fork: blocks mined by
+    /// `sync` and `reorg`, released by `release_private` and `catch_up`, or imported by `heal_from`
+    /// and `bulk_import_ops`.  The count never goes down; the operations of reorged-out blocks
+    /// still count, and count again if mined anew.
+    pub fn total_ops_mined(&self) -> u64 {
+        self.total_ops_mined
+    }
+
+    /// Rough size in bytes of what the controller holds in memory: the queue, the processing
+    /// log, the cached chain tip and the bookkeeping of orphans, equivocations and annotations.
+    /// The sortition DB is not counted.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let op_heap_bytes = |operation: &BlockstackOperationType| match operation {
+            BlockstackOperationType::LeaderKeyRegister(op) => op.memo.capacity(),
+            BlockstackOperationType::LeaderBlockCommit(op) => op.memo.capacity()
+                + op.input.public_keys.capacity() * mem::size_of::<Secp256k1PublicKey>(),
+            BlockstackOperationType::UserBurnSupport(_) => 0
+        };
+
+        let queue_bytes: usize = self.queued_operations.iter()
+            .map(|queued| mem::size_of::<QueuedOperation>() + op_heap_bytes(&queued.operation))
+            .sum();
+        let processing_log_bytes = self.processing_log.len() * mem::size_of::<OpProcessingRecord>();
+        let chain_tip_bytes = match self.chain_tip {
+            Some(ref chain_tip) => mem::size_of::<BurnchainTip>()
+                + chain_tip.state_transition.accepted_ops.iter()
+                    .map(|op| mem::size_of::<BlockstackOperationType>() + op_heap_bytes(op))
+                    .sum::<usize>()
+                + chain_tip.state_transition.consumed_leader_keys.len() * mem::size_of::<LeaderKeyRegisterOp>()
+                + chain_tip.state_transition.burn_dist.len() * mem::size_of::<BurnSamplePoint>(),
+            None => 0
+        };
+        let orphan_bytes = self.last_orphaned_ops.len() * mem::size_of::<Txid>();
+        let equivocation_bytes: usize = self.last_equivocations.iter()
+            .map(|(_, txids)| mem::size_of::<(VRFPublicKey, Vec<Txid>)>() + txids.len() * mem::size_of::<Txid>())
+            .sum();
+        let annotation_bytes: usize = self.annotations.values()
+            .map(|note| mem::size_of::<(u64, String)>() + note.capacity())
+            .sum();
+
+        mem::size_of::<MocknetController>() + queue_bytes + processing_log_bytes + chain_tip_bytes
+            + orphan_bytes + equivocation_bytes + annotation_bytes
+    }
+
+    /// Number of operations mined by `sync` for each latency, in blocks from the chain tip at
+    /// submission to the block including them; 1 means the next block.
+    pub fn inclusion_latency_histogram(&self) -> BTreeMap<u64, usize> {
+        self.inclusion_latencies.clone()
+    }
+
+    /// Attach a free-form note to the canonical block at `height`.  Notes are bookkeeping only:
+    /// they never affect consensus, and are dropped when their block is reorged out.
+    pub fn annotate_block(&mut self, height: u64, note: String) {
+        if height > self.get_chain_tip_ref().block_snapshot.block_height {
+            debug!("Not annotating block {}: it has not been mined yet", height);
+            return;
+        }
+        self.annotations.insert(height, note);
+    }
+
+    /// The note attached to the canonical block at `height`, if any.
+    pub fn block_annotation(&self, height: u64) -> Option<&str> {
+        self.annotations.get(&height).map(|note| note.as_str())
+    }
+
+    /// Read the chain tip at the requested consistency level.
+    /// With `mocknet.disable_tip_cache`, every read is `Fresh`.
+    pub fn get_chain_tip_with(&self, level: ConsistencyLevel) -> Result<BurnchainTip, BurnchainControllerError> {
+        let chain_tip = self.get_chain_tip_ref();
+        match level {
+            ConsistencyLevel::Cached if !self.config.mocknet.disable_tip_cache => Ok(chain_tip.clone()),
+            _ => {
+                let canonical = SortitionDB::get_canonical_burn_chain_tip_stubbed(self.sortdb_ref().conn())?;
+                if canonical.burn_header_hash == chain_tip.block_snapshot.burn_header_hash {
+                    return Ok(chain_tip.clone());
+                }
+                self.tip_from_snapshot(canonical)
+            }
+        }
+    }
+
+    /// Number of blocks between `ancestor` and the chain tip,
+    /// or `None` if `ancestor` is not on the canonical fork.
+    pub fn distance_from(&self, ancestor: &BlockSnapshot) -> Result<Option<u64>, BurnchainControllerError> {
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        if ancestor.block_height > tip_height {
+            return Ok(None);
+        }
+        let canonical = self.canonical_snapshot_at(ancestor.block_height)?;
+        if canonical.burn_header_hash != ancestor.burn_header_hash {
+            return Ok(None);
+        }
+        Ok(Some(tip_height - ancestor.block_height))
+    }
+
+    /// Snapshot of the block `snapshot` was mined on, or `None` for the first block.
+    pub fn parent_snapshot(&self, snapshot: &BlockSnapshot) -> Result<Option<BlockSnapshot>, BurnchainControllerError> {
+        if snapshot.block_height <= self.sortdb_ref().first_block_height {
+            return Ok(None);
+        }
+        self.get_snapshot_by_hash(&snapshot.parent_burn_header_hash)
+    }
+
+    /// Render every known block, orphaned branches included, as a Graphviz DOT digraph.
+    /// Nodes are labeled with their height and hash prefix, edges point from child to parent,
+    /// and the canonical fork is drawn bold while orphaned blocks are dashed.
+    pub fn to_dot(&self) -> Result<String, BurnchainControllerError> {
+        let snapshots = self.sortdb_ref().get_all_snapshots()?;
+        let mut canonical = HashSet::new();
+        for snapshot in snapshots.iter() {
+            if self.distance_from(snapshot)?.is_some() {
+                canonical.insert(snapshot.burn_header_hash.clone());
+            }
+        }
+
+        let mut dot = String::from("digraph mocknet {\n");
+        let mut known = HashSet::new();
+        for snapshot in snapshots.iter() {
+            let hash = snapshot.burn_header_hash.to_hex();
+            let style = if canonical.contains(&snapshot.burn_header_hash) { "bold" } else { "dashed" };
+            dot.push_str(&format!("    \"{}\" [label=\"{}\\n{}\", style={}];\n",
+                                  hash, snapshot.block_height, &hash[..8], style));
+
+            // Snapshots come ordered by height, so a known parent has been emitted already
+            if known.contains(&snapshot.parent_burn_header_hash) {
+                dot.push_str(&format!("    \"{}\" -> \"{}\" [style={}];\n",
+                                      hash, snapshot.parent_burn_header_hash.to_hex(), style));
+            }
+            known.insert(snapshot.burn_header_hash.clone());
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Run SQLite `VACUUM` on the sortition DB, returning how many bytes the file shrank by.
+    pub fn vacuum(&mut self) -> Result<u64, BurnchainControllerError> {
+        let data_path = PathBuf::from(self.config.get_burn_db_file_path()).join("data.db");
+        let size_before = fs::metadata(&data_path).map_err(db_error::IOError)?.len();
+        self.sortdb_mut().conn().execute_batch("VACUUM")
+            .map_err(db_error::SqliteError)?;
+        let size_after = fs::metadata(&data_path).map_err(db_error::IOError)?.len();
+        Ok(size_before.saturating_sub(size_after))
+    }
+
+    /// Copy the sortition DB into the directory `path`, which can then be opened with
+    /// `SortitionDB::open` like the burnchain DB of a regular node.
+    /// Both of its SQLite files are copied as of the last committed block.
+    pub fn export_sortition_db(&self, path: &Path) -> Result<(), BurnchainControllerError> {
+        let db_path = PathBuf::from(self.config.get_burn_db_file_path());
+        fs::create_dir_all(path).map_err(db_error::IOError)?;
+        for file_name in ["data.db", "marf"].iter() {
+            fs::copy(db_path.join(file_name), path.join(file_name)).map_err(db_error::IOError)?;
+        }
+        Ok(())
+    }
+
+    /// Check that every block on the canonical fork points at its predecessor's header hash.
+    pub fn verify_header_chain(&self) -> Result<bool, BurnchainControllerError> {
+        match self.find_header_chain_break()? {
+            Some(height) => {
+                warn!("Header chain is broken at height {}", height);
+                Ok(false)
+            },
+            None => Ok(true)
+        }
+    }
+
+    /// Height of the first canonical block whose parent hash does not match its predecessor.
+    pub fn find_header_chain_break(&self) -> Result<Option<u64>, BurnchainControllerError> {
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        let mut parent = self.canonical_snapshot_at(self.sortdb_ref().first_block_height)?;
+        for height in (parent.block_height + 1)..(tip_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            if snapshot.parent_burn_header_hash != parent.burn_header_hash {
+                return Ok(Some(height));
+            }
+            parent = snapshot;
+        }
+        Ok(None)
+    }
+
+    /// `start`, returning an error instead of panicking when `verify_on_resume` finds the
+    /// resumed chain inconsistent, or when the genesis leader keys can't be registered.
+    pub fn try_start(&mut self) -> Result<BurnchainTip, BurnchainControllerError> {
+        let db = match SortitionDB::connect(&self.config.get_burn_db_file_path(), self.burnchain.first_block_height, &BurnchainHeaderHash([0u8; 32]), get_epoch_time_secs(), true) {
+            Ok(db) => db,
+            Err(_) => panic!("Error while connecting to burnchain db")
+        };
+        let block_snapshot = SortitionDB::get_canonical_burn_chain_tip_stubbed(db.conn())
+            .expect("FATAL: failed to get canonical chain tip");
+
+        self.db = Some(db);
+
+        let genesis_state = BurnchainTip {
+            block_snapshot,
+            state_transition: BurnchainStateTransition {
+                burn_dist: vec![],
+                accepted_ops: vec![],
+                consumed_leader_keys: vec![]
+            },
+            received_at: Instant::now(),
+        };
+        self.chain_tip = Some(genesis_state.clone());
+
+        let at_genesis = genesis_state.block_snapshot.block_height == self.burnchain.first_block_height;
+        if self.config.mocknet.verify_on_resume && !at_genesis {
+            self.verify_chain()?;
+        }
+        if self.config.mocknet.genesis_leader_keys > 0 && at_genesis {
+            return self.mine_genesis_leader_keys();
+        }
+
+        Ok(genesis_state)
+    }
+
+    /// Check that every canonical block links to its parent's header hash and that its stored
+    /// sortition hash matches the one recomputed from its parent.
+    pub fn verify_chain(&self) -> Result<(), BurnchainControllerError> {
+        if let Some(height) = self.find_header_chain_break()? {
+            return Err(BurnchainControllerError::InvalidState(format!("header chain is broken at height {}", height)));
+        }
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        for height in (self.sortdb_ref().first_block_height + 1)..(tip_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            if self.sortition_hash(height)? != Some(snapshot.sortition_hash) {
+                return Err(BurnchainControllerError::InvalidState(format!("sortition hash mismatch at height {}", height)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-run the checks of `explain_validation` on every operation of the canonical fork,
+    /// each against the block it was mined on top of, and check that every block links to
+    /// its parent's header hash.  Stops at the first violation, lowest height first.
+    pub fn validate_full_chain(&self) -> Result<(), ChainValidationError> {
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        let mut parent = self.canonical_snapshot_at(self.sortdb_ref().first_block_height)?;
+        for height in (parent.block_height + 1)..(tip_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            if snapshot.parent_burn_header_hash != parent.burn_header_hash {
+                return Err(ChainValidationError::HeaderChainBroken { block_height: height });
+            }
+            for operation in self.get_block_ops(&snapshot)?.iter() {
+                let report = self.explain_validation_after(operation, &parent);
+                if let Some(check) = report.first_failure() {
+                    return Err(ChainValidationError::InvalidOperation {
+                        block_height: height,
+                        txid: operation.txid(),
+                        rule: check.rule,
+                        detail: check.detail.clone(),
+                    });
+                }
+            }
+            parent = snapshot;
+        }
+        Ok(())
+    }
+
+    /// Hash of the canonical fork: the header hash, consensus hash and serialized operations
+    /// of every block from the first block height to the tip, folded together in order.  The
+    /// operations are needed because neither hash depends on their contents.  Runs that mine
+    /// the same blocks with the same operations produce the same fingerprint.
+    pub fn chain_fingerprint(&self) -> Result<[u8; 32], BurnchainControllerError> {
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        let mut fingerprint = [0u8; 32];
+        for height in self.sortdb_ref().first_block_height..(tip_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            let mut preimage = fingerprint.to_vec();
+            preimage.extend_from_slice(snapshot.burn_header_hash.as_bytes());
+            preimage.extend_from_slice(snapshot.consensus_hash.as_bytes());
+            preimage.extend_from_slice(&serde_json::to_vec(&self.get_block_ops(&snapshot)?)
+                .expect("FATAL: failed to serialize operations"));
+            fingerprint = Sha256Sum::from_data(&preimage).0;
+        }
+        Ok(fingerprint)
+    }
+
+    fn get_snapshot_by_hash(&self, burn_header_hash: &BurnchainHeaderHash) -> Result<Option<BlockSnapshot>, BurnchainControllerError> {
+        let snapshot = SortitionDB::get_block_snapshot(self.sortdb_ref().conn(), &SortitionId::stubbed(burn_header_hash))?;
+        Ok(snapshot)
+    }
+
+    /// Chain tip for a block that was processed earlier.
+    /// Only the accepted operations of its state transition can be recovered.
+    fn tip_from_snapshot(&self, block_snapshot: BlockSnapshot) -> Result<BurnchainTip, BurnchainControllerError> {
+        let accepted_ops = self.get_block_ops(&block_snapshot)?;
+        Ok(BurnchainTip {
+            block_snapshot,
+            state_transition: BurnchainStateTransition {
+                burn_dist: vec![],
+                accepted_ops,
+                consumed_leader_keys: vec![]
+            },
+            received_at: Instant::now()
+        })
+    }
+
+    /// Dump the operations of the canonical fork as CSV, one row per operation in mining order.
+    /// The burn fee is left empty for key registrations, which burn nothing.
+    pub fn export_ops_csv(&self) -> Result<String, BurnchainControllerError> {
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        let first_block_height = self.sortdb_ref().first_block_height;
+
+        let mut csv = String::from("block_height,txid,op_type,burn_fee,address\n");
+        for height in (first_block_height + 1)..(tip_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            for op in self.get_block_ops(&snapshot)?.iter() {
+                let (burn_fee, address) = match op {
+                    BlockstackOperationType::LeaderKeyRegister(op) => (None, op.address.clone()),
+                    BlockstackOperationType::LeaderBlockCommit(op) => (Some(op.burn_fee), Keychain::address_from_burnchain_signer(&op.input)),
+                    BlockstackOperationType::UserBurnSupport(op) => (Some(op.burn_fee), op.address.clone()),
+                };
+                let burn_fee = burn_fee.map(|fee| fee.to_string()).unwrap_or_default();
+                csv.push_str(&format!("{},{},{},{},{}\n",
+                                      height, op.txid().to_hex(), OpTypeKind::of(op).name(), burn_fee, address));
+            }
+        }
+        Ok(csv)
+    }
+
+    /// Serialize the mined blocks and the queue as JSON.
+    /// With `retain_depth`, only the canonical fork and the branches forking off within
+    /// the last `retain_depth` blocks are kept, which still allows reorgs up to that depth.
+    pub fn export_state(&self, retain_depth: Option<u64>) -> Result<String, BurnchainControllerError> {
+        let chain_tip = self.get_chain_tip_ref().block_snapshot.clone();
+        let first_block_height = self.sortdb_ref().first_block_height;
+        let oldest_fork_height = match retain_depth {
+            Some(depth) => chain_tip.block_height.saturating_sub(depth),
+            None => first_block_height
+        };
+
+        let mut blocks = vec![];
+        for snapshot in self.sortdb_ref().get_all_snapshots()?.into_iter() {
+            if snapshot.block_height <= first_block_height {
+                continue;
+            }
+            let fork_point = self.find_fork_point(&snapshot, &chain_tip)?;
+            if fork_point.block_height < snapshot.block_height && fork_point.block_height < oldest_fork_height {
+                continue;
+            }
+            blocks.push(ExportedBlock {
+                block_height: snapshot.block_height,
+                block_hash: snapshot.burn_header_hash,
+                parent_block_hash: snapshot.parent_burn_header_hash,
+                timestamp: snapshot.burn_header_timestamp,
+                ops: self.get_block_ops(&snapshot)?,
+            });
+        }
+
+        let state = MocknetState {
+            chain_tip: chain_tip.burn_header_hash,
+            blocks,
+            queued_operations: self.queued_operations.iter().map(|queued| queued.operation.clone()).collect(),
+            forks_mined: self.forks_mined,
+        };
+        serde_json::to_string(&state)
+            .map_err(|e| BurnchainControllerError::InvalidState(format!("failed to serialize state: {}", e)))
+    }
+
+    /// Start a new controller and replay a state produced by `export_state` into it.
+    pub fn import_state(config: Config, state: &str) -> Result<MocknetController, BurnchainControllerError> {
+        let state: MocknetState = serde_json::from_str(state)
+            .map_err(|e| BurnchainControllerError::InvalidState(format!("failed to parse state: {}", e)))?;
+
+        let mut controller = MocknetController::new(config);
+        controller.start();
+
+        let mut chain_tip = None;
+        for block in state.blocks.into_iter() {
+            let parent = controller.get_snapshot_by_hash(&block.parent_block_hash)?
+                .ok_or(BurnchainControllerError::InvalidState(format!("missing parent of block {}", block.block_hash)))?;
+            let block_header = BurnchainBlockHeader {
+                block_height: block.block_height,
+                block_hash: block.block_hash,
+                parent_block_hash: block.parent_block_hash,
+                num_txs: 0,
+                timestamp: block.timestamp,
+            };
+            let tip = controller.append_block(&parent, &block_header, block.ops)?;
+            if block.block_hash == state.chain_tip {
+                chain_tip = Some(tip);
+            }
+        }
+
+        if let Some(chain_tip) = chain_tip {
+            controller.chain_tip = Some(chain_tip);
+        } else if state.chain_tip != controller.get_chain_tip_ref().block_snapshot.burn_header_hash {
+            return Err(BurnchainControllerError::InvalidState(format!("missing chain tip {}", state.chain_tip)));
+        }
+        for operation in state.queued_operations.into_iter() {
+            controller.enqueue(operation);
+        }
+        controller.forks_mined = state.forks_mined;
+
+        Ok(controller)
+    }
+
+    /// Mine historical operations at the heights they are paired with, in one pass on top of
+    /// the chain tip.  Operations sharing a height go into one block in the given order, and the
+    /// heights in between get empty blocks.  Heights must be above the chain tip.
+    /// Operations are not screened like submissions and never wait in the queue; the consensus
+    /// rules still apply.  Each block is still committed in a sortition DB transaction of its
+    /// own, as the sortition MARF only takes one block per transaction.
+    pub fn bulk_import_ops(&mut self, ops: Vec<(u64, BlockstackOperationType)>) -> Result<BurnchainTip, BurnchainControllerError> {
+        let mut chain_tip = self.get_chain_tip();
+        let mut ops_by_height: BTreeMap<u64, Vec<BlockstackOperationType>> = BTreeMap::new();
+        for (height, op) in ops.into_iter() {
+            if height <= chain_tip.block_snapshot.block_height {
+                return Err(BurnchainControllerError::InvalidState(
+                    format!("cannot import operation {} at height {}: chain tip is at {}", op.txid(), height, chain_tip.block_snapshot.block_height)));
+            }
+            ops_by_height.entry(height).or_insert_with(Vec::new).push(op);
+        }
+        let last_height = match ops_by_height.keys().next_back() {
+            Some(height) => *height,
+            None => return Ok(chain_tip)
+        };
+
+        for height in (chain_tip.block_snapshot.block_height + 1)..(last_height + 1) {
+            let block_ops = ops_by_height.remove(&height).unwrap_or_default();
+            chain_tip = self.mine_block(&chain_tip.block_snapshot, block_ops, &[])?;
+            self.chain_tip = Some(chain_tip.clone());
+            self.notify_finalized_blocks()?;
+        }
+        Ok(chain_tip)
+    }
+
+    /// Txids of the operations contained in the blocks dropped by the last reorg.
+    pub fn last_orphaned_ops(&self) -> &[Txid] {
+        &self.last_orphaned_ops
+    }
+
+    /// Snapshot at `height` on the fork of the current chain tip.
+    fn canonical_snapshot_at(&self, height: u64) -> Result<BlockSnapshot, BurnchainControllerError> {
+        let chain_tip = self.get_chain_tip_ref();
+        let ic = self.sortdb_ref().index_conn();
+        SortitionDB::get_ancestor_snapshot(&ic, height, &chain_tip.block_snapshot.sortition_id)?
+            .ok_or(BurnchainControllerError::DBError(db_error::NotFoundError))
+    }
+
+    /// All the operations accepted into the given block, in vtxindex order.
+    fn get_block_ops(&self, snapshot: &BlockSnapshot) -> Result<Vec<BlockstackOperationType>, BurnchainControllerError> {
+        let conn = self.sortdb_ref().conn();
+        let mut ops: Vec<BlockstackOperationType> = vec![];
+        for op in SortitionDB::get_leader_keys_by_block(conn, &snapshot.sortition_id)?.into_iter() {
+            ops.push(BlockstackOperationType::LeaderKeyRegister(op));
+        }
+        for op in SortitionDB::get_block_commits_by_block(conn, &snapshot.sortition_id)?.into_iter() {
+            ops.push(BlockstackOperationType::LeaderBlockCommit(op));
+        }
+        for op in SortitionDB::get_user_burns_by_block(conn, &snapshot.sortition_id)?.into_iter() {
+            ops.push(BlockstackOperationType::UserBurnSupport(op));
+        }
+        ops.sort_by_key(|op| op.vtxindex());
+        Ok(ops)
+    }
+
+    /// The block commit that won the sortition of `snapshot`, if it had one.
+    pub fn winning_commit_at(&self, snapshot: &BlockSnapshot) -> Result<Option<LeaderBlockCommitOp>, BurnchainControllerError> {
+        if !snapshot.sortition || snapshot.winning_block_txid == Txid([0u8; 32]) {
+            return Ok(None);
+        }
+        let ic = self.sortdb_ref().index_handle(&snapshot.sortition_id);
+        Ok(ic.get_block_commit(&snapshot.winning_block_txid, &snapshot.burn_header_hash)?)
+    }
+
+    /// VRF public key of the leader whose commit won the sortition at `height` on the canonical fork.
+    /// Returns `None` if no sortition took place at that height.
+    pub fn sortition_winner_pubkey(&self, height: u64) -> Result<Option<VRFPublicKey>, BurnchainControllerError> {
+        let snapshot = self.canonical_snapshot_at(height)?;
+        if !snapshot.sortition || snapshot.winning_block_txid == Txid([0u8; 32]) {
+            return Ok(None);
+        }
+
+        let ic = self.sortdb_ref().index_handle(&snapshot.sortition_id);
+        let winning_commit = match ic.get_block_commit(&snapshot.winning_block_txid, &snapshot.burn_header_hash)? {
+            Some(commit) => commit,
+            None => return Ok(None)
+        };
+        let leader_key = ic.get_leader_key_at(winning_commit.key_block_ptr as u64, winning_commit.key_vtxindex as u32)?;
+        Ok(leader_key.map(|key| key.public_key))
+    }
+
+    /// Recompute the sortition hash of the canonical block at `height` from its parent's
+    /// sortition hash, its header hash and, if it had a sortition, the winner's new VRF seed.
+    /// `None` above the chain tip.
+    pub fn sortition_hash(&self, height: u64) -> Result<Option<SortitionHash>, BurnchainControllerError> {
+        if height > self.get_chain_tip_ref().block_snapshot.block_height {
+            return Ok(None);
+        }
+        let snapshot = self.canonical_snapshot_at(height)?;
+        let parent = match self.parent_snapshot(&snapshot)? {
+            Some(parent) => parent,
+            // Nothing to derive the first block's hash from
+            None => return Ok(Some(snapshot.sortition_hash))
+        };
+
+        let sortition_hash = parent.sortition_hash.mix_burn_header(&snapshot.burn_header_hash);
+        if !snapshot.sortition {
+            return Ok(Some(sortition_hash));
+        }
+
+        let ic = self.sortdb_ref().index_handle(&snapshot.sortition_id);
+        let winning_commit = ic.get_block_commit(&snapshot.winning_block_txid, &snapshot.burn_header_hash)?
+            .ok_or(BurnchainControllerError::DBError(db_error::NotFoundError))?;
+        Ok(Some(sortition_hash.mix_VRF_seed(&winning_commit.new_seed)))
+    }
+
+    /// Deterministic keychain for the miner labeled `label` ("alice", "bob", ...).
+    /// The same label yields the same keychain in every run with the same `chain_seed`.
+    pub fn keychain_for(&self, label: &str) -> Keychain {
+        let mut seed = self.config.mocknet.chain_seed.clone();
+        seed.extend_from_slice(b"address-book");
+        seed.extend_from_slice(label.as_bytes());
+        Keychain::default(Sha256Sum::from_data(&seed).as_bytes().to_vec())
+    }
+
+    /// Address of the `keychain_for(label)` keychain.
+    pub fn address_for(&self, label: &str) -> StacksAddress {
+        self.keychain_for(label).get_address()
+    }
+
+    /// Deterministic keychain owning the `index`-th key registered by `mocknet.genesis_leader_keys`.
+    /// Its first VRF key rotation yields the registered key.
+    pub fn genesis_keychain(&self, index: u64) -> Keychain {
+        let mut seed = self.config.mocknet.chain_seed.clone();
+        seed.extend_from_slice(b"genesis-leader-key");
+        seed.extend_from_slice(&index.to_be_bytes());
+        Keychain::default(Sha256Sum::from_data(&seed).as_bytes().to_vec())
+    }
+
+    fn mine_genesis_leader_keys(&mut self) -> Result<BurnchainTip, BurnchainControllerError> {
+        let genesis = self.get_chain_tip_ref().block_snapshot.clone();
+        let payloads = (0..self.config.mocknet.genesis_leader_keys).map(|index| {
+            let mut keychain = self.genesis_keychain(index);
+            BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
+                public_key: keychain.rotate_vrf_keypair(genesis.block_height),
+                memo: vec![],
+                address: keychain.get_address(),
+                consensus_hash: genesis.consensus_hash.clone(),
+                vtxindex: 0,
+                txid: Txid([0u8; 32]),
+                block_height: 0,
+                burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+            })
+        }).collect();
+
+        let chain_tip = self.mine_block(&genesis, payloads, &[])?;
+        self.chain_tip = Some(chain_tip.clone());
+        Ok(chain_tip)
+    }
+
+    /// Leader keys registered on the canonical fork that no block commit has consumed yet,
+    /// oldest first.
+    pub fn available_leader_keys(&self) -> Result<Vec<LeaderKeyRegisterOp>, BurnchainControllerError> {
+        let chain_tip = &self.get_chain_tip_ref().block_snapshot;
+        let handle = self.sortdb_ref().index_handle(&chain_tip.sortition_id);
+
+        let mut available_keys = vec![];
+        for height in (self.sortdb_ref().first_block_height + 1)..(chain_tip.block_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            for key in SortitionDB::get_leader_keys_by_block(self.sortdb_ref().conn(), &snapshot.sortition_id)?.into_iter() {
+                if !handle.is_leader_key_consumed(&key)? {
+                    available_keys.push(key);
+                }
+            }
+        }
+        Ok(available_keys)
+    }
+
+    /// Winning Stacks block hash at each height of `[start, end]` on the canonical fork,
+    /// `None` for blocks without a sortition.
+    pub fn sortition_winners_in_range(&self, start: u64, end: u64) -> Result<Vec<(u64, Option<BlockHeaderHash>)>, BurnchainControllerError> {
+        let mut winners = vec![];
+        for height in start..(end + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            let winner = if snapshot.sortition && snapshot.winning_block_txid != Txid([0u8; 32]) {
+                Some(snapshot.winning_stacks_block_hash)
+            } else {
+                None
+            };
+            winners.push((height, winner));
+        }
+        Ok(winners)
+    }
+
+    /// Sortitions won on the canonical fork by each miner, identified by the address of the
+    /// leader key its winning commits used.
+    pub fn miner_win_stats(&self) -> Result<HashMap<StacksAddress, u64>, BurnchainControllerError> {
+        let mut wins = HashMap::new();
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        for height in (self.sortdb_ref().first_block_height + 1)..(tip_height + 1) {
+            let snapshot = self.canonical_snapshot_at(height)?;
+            let winning_commit = match self.winning_commit_at(&snapshot)? {
+                Some(commit) => commit,
+                None => continue
+            };
+            let ic = self.sortdb_ref().index_handle(&snapshot.sortition_id);
+            let leader_key = ic.get_leader_key_at(winning_commit.key_block_ptr as u64, winning_commit.key_vtxindex as u32)?
+                .ok_or(BurnchainControllerError::DBError(db_error::NotFoundError))?;
+            *wins.entry(leader_key.address).or_insert(0) += 1;
+        }
+        Ok(wins)
+    }
+
+    /// Reward cycle containing burn block `height`, counted from the first block height.
+    pub fn reward_cycle_of_height(&self, height: u64) -> u64 {
+        height.saturating_sub(self.burnchain.first_block_height) / self.config.mocknet.reward_cycle_length
+    }
+
+    /// Estimated seconds until the first block of the next reward cycle,
+    /// or `None` if `mocknet.block_time_secs` is not configured.
+    pub fn secs_to_next_reward_cycle(&self) -> Option<u64> {
+        let block_time_secs = self.config.mocknet.block_time_secs?;
+        let tip_height = self.get_chain_tip_ref().block_snapshot.block_height;
+        let next_cycle_start = self.burnchain.first_block_height
+            + (self.reward_cycle_of_height(tip_height) + 1) * self.config.mocknet.reward_cycle_length;
+        Some((next_cycle_start - tip_height) * block_time_secs)
+    }
+
+    /// Coinbase, in microSTX, that the emission schedule awards at burn block `height`.
+    pub fn coinbase_reward_at_height(&self, height: u64) -> u64 {
+        let offset = height.saturating_sub(self.burnchain.first_block_height);
+        self.config.mocknet.coinbase_schedule.iter()
+            .take_while(|(start_height, _)| *start_height <= offset)
+            .last()
+            .map(|(_, reward)| *reward)
+            .unwrap_or(0)
+    }
+
+    /// Burn target of the block after the chain tip, per `mocknet.burn_target_window`: the mean
+    /// burned by the most recent blocks, or the constant `mocknet.burn_target`.
+    pub fn next_burn_target(&self) -> Result<u64, BurnchainControllerError> {
+        let window = match self.config.mocknet.burn_target_window {
+            Some(window) if window > 0 => window,
+            _ => return Ok(self.config.mocknet.burn_target)
+        };
+        let chain_tip = &self.get_chain_tip_ref().block_snapshot;
+        let num_blocks = cmp::min(window, chain_tip.block_height.saturating_sub(self.sortdb_ref().first_block_height));
+        if num_blocks == 0 {
+            return Ok(self.config.mocknet.burn_target);
+        }
+        let window_start = self.canonical_snapshot_at(chain_tip.block_height - num_blocks)?;
+        Ok((chain_tip.total_burn - window_start.total_burn) / num_blocks)
+    }
+
+    /// Leader keys that made several block commits on the same parent in the last mined block,
+    /// with the txids of those commits.
+    pub fn last_equivocations(&self) -> &[(VRFPublicKey, Vec<Txid>)] {
+        &self.last_equivocations
+    }
+
+    /// Record the equivocating commits among the stamped `ops` of a block mined on `parent`,
+    /// dropping all but the first commit of each equivocation if `mocknet.reject_equivocations` is set.
+    fn screen_equivocations(&mut self, parent: &BlockSnapshot, ops: Vec<BlockstackOperationType>) -> Result<Vec<BlockstackOperationType>, BurnchainControllerError> {
+        let mut commits_by_key_and_parent: Vec<((u32, u16, u32, u16), Vec<Txid>)> = vec![];
+        for op in ops.iter() {
+            if let BlockstackOperationType::LeaderBlockCommit(commit) = op {
+                let group = (commit.key_block_ptr, commit.key_vtxindex, commit.parent_block_ptr, commit.parent_vtxindex);
+                match commits_by_key_and_parent.iter_mut().find(|(other, _)| *other == group) {
+                    Some((_, txids)) => txids.push(commit.txid.clone()),
+                    None => commits_by_key_and_parent.push((group, vec![commit.txid.clone()]))
+                }
+            }
+        }
+
+        let handle = self.sortdb_ref().index_handle(&parent.sortition_id);
+        let mut equivocations = vec![];
+        for ((key_block_ptr, key_vtxindex, _, _), txids) in commits_by_key_and_parent.into_iter() {
+            if txids.len() < 2 {
+                continue;
+            }
+            // Commits with an unknown key are invalid anyway
+            if let Some(key) = handle.get_leader_key_at(key_block_ptr as u64, key_vtxindex as u32)? {
+                warn!("Leader key {} equivocated with block commits {:?}", key.public_key.to_hex(), txids);
+                equivocations.push((key.public_key, txids));
+            }
+        }
+
+        let ops = if self.config.mocknet.reject_equivocations {
+            let rejected: Vec<_> = equivocations.iter()
+                .flat_map(|(_, txids)| txids[1..].iter().cloned())
+                .collect();
+            ops.into_iter().filter(|op| !rejected.contains(&op.txid())).collect()
+        } else {
+            ops
+        };
+        self.last_equivocations = equivocations;
+        Ok(ops)
+    }
+
+    fn get_chain_tip_ref(&self) -> &BurnchainTip {
+        self.chain_tip.as_ref().expect("BUG: did not start the burnchain")
+    }
+
+    /// Mine a block containing `payloads` on top of `parent`.
+    /// `salt` distinguishes sibling blocks mined on the same parent.
+    fn mine_block(&mut self, parent: &BlockSnapshot, payloads: Vec<BlockstackOperationType>, salt: &[u8]) -> Result<BurnchainTip, BurnchainControllerError> {
+        let timestamp = self.next_block_timestamp(parent.burn_header_timestamp);
+        let next_block_header = self.build_next_block_header(parent.block_height, &parent.burn_header_hash, salt, timestamp);
+
+        let ops = MocknetController::stamp_operations(payloads, &next_block_header);
+        let ops = self.screen_equivocations(parent, ops)?;
+
+        self.append_block(parent, &next_block_header, ops)
+    }
+
+    /// Mine `payloads` on top of `parent` in a block whose sortition winner is the commit
+    /// whose burn range contains `target`.
+    fn mine_block_into_range(&mut self, parent: &BlockSnapshot, payloads: Vec<BlockstackOperationType>, target: Uint256) -> Result<BurnchainTip, BurnchainControllerError> {
+        self.mine_block_selecting(parent, payloads, |block_snapshot, state_transition| {
+            let burn_dist = &state_transition.burn_dist;
+            match burn_dist.iter().find(|point| point.range_start <= target && target < point.range_end).or(burn_dist.last()) {
+                Some(point) => point.candidate.txid == block_snapshot.winning_block_txid,
+                None => true
+            }
+        })
+    }
+
+    /// Mine a block whose sortition is won by a commit of `winner`, unless none of its commits
+    /// are accepted into the block.
+    fn mine_block_won_by(&mut self, parent: &BlockSnapshot, payloads: Vec<BlockstackOperationType>, winner: BurnchainSigner) -> Result<BurnchainTip, BurnchainControllerError> {
+        self.mine_block_selecting(parent, payloads, |block_snapshot, state_transition| {
+            let mut winner_commits = state_transition.accepted_ops.iter()
+                .filter_map(|op| match op {
+                    BlockstackOperationType::LeaderBlockCommit(op) if op.input == winner => Some(op),
+                    _ => None
+                })
+                .peekable();
+            winner_commits.peek().is_none() || winner_commits.any(|op| op.txid == block_snapshot.winning_block_txid)
+        })
+    }
+
+    /// Grind block headers until `accept` approves of the resulting sortition.
+    fn mine_block_selecting<F>(&mut self, parent: &BlockSnapshot, payloads: Vec<BlockstackOperationType>, accept: F) -> Result<BurnchainTip, BurnchainControllerError>
+    where F: Fn(&BlockSnapshot, &BurnchainStateTransition) -> bool {
+        let timestamp = self.next_block_timestamp(parent.burn_header_timestamp);
+        for attempt in 0..MAX_SORTITION_OVERRIDE_ATTEMPTS {
+            let mut salt = b"sortition".to_vec();
+            salt.extend_from_slice(&attempt.to_be_bytes());
+            let block_header = self.build_next_block_header(parent.block_height, &parent.burn_header_hash, &salt, timestamp);
+            let ops = MocknetController::stamp_operations(payloads.clone(), &block_header);
+            let ops = self.screen_equivocations(parent, ops)?;
+
+            if let Some(tip) = self.append_block_if(parent, &block_header, ops, &accept)? {
+                return Ok(tip);
+            }
+        }
+        Err(BurnchainControllerError::SortitionOverrideFailed(format!("no header within {} attempts selects the target", MAX_SORTITION_OVERRIDE_ATTEMPTS)))
+    }
+
+    fn append_block(&mut self, parent: &BlockSnapshot, block_header: &BurnchainBlockHeader, ops: Vec<BlockstackOperationType>) -> Result<BurnchainTip, BurnchainControllerError> {
+        let tip = self.append_block_if(parent, block_header, ops, |_, _| true)?;
+        Ok(tip.expect("BUG: unconditional block was not appended"))
+    }
+
+    /// Process a block of already stamped operations on top of `parent`, keeping it only if
+    /// `accept` approves of the outcome.  Returns `None` if the block was discarded.
+    fn append_block_if<F>(&mut self, parent: &BlockSnapshot, block_header: &BurnchainBlockHeader, ops: Vec<BlockstackOperationType>, accept: F) -> Result<Option<BurnchainTip>, BurnchainControllerError>
+    where F: Fn(&BlockSnapshot, &BurnchainStateTransition) -> bool {
+        // Include txs in a new block
+        let (block_snapshot, state_transition) = {
+            let burn_db = self.db.as_mut().expect("BUG: did not instantiate burn DB");
+            let mut burn_tx = SortitionHandleTx::begin(burn_db, &parent.sortition_id)?;
+            let (block_snapshot, state_transition) = burn_tx.process_block_ops(
+                &self.burnchain, parent, block_header, ops.clone())?;
+            if !accept(&block_snapshot, &state_transition) {
+                // dropping the transaction discards the block
+                return Ok(None);
+            }
+            burn_tx.commit()?;
+            (block_snapshot, state_transition)
+        };
+
+        self.record_processed_ops(&ops, &state_transition);
+        self.total_ops_mined += state_transition.accepted_ops.len() as u64;
+        for sink in self.op_sinks.iter_mut() {
+            sink.on_ops_mined(block_snapshot.block_height, &state_transition.accepted_ops);
+        }
+
+        self.emit_event(ControllerEvent::BlockMined {
+            block_height: block_snapshot.block_height,
+            burn_header_hash: block_snapshot.burn_header_hash,
+        });
+        for op in ops.iter() {
+            let txid = op.txid();
+            let block_height = block_snapshot.block_height;
+            if state_transition.accepted_ops.iter().any(|accepted_op| accepted_op.txid() == txid) {
+                self.emit_event(ControllerEvent::OpAccepted { block_height, txid });
+            } else {
+                self.emit_event(ControllerEvent::OpRejected { block_height, txid });
+            }
+        }
+
+        Ok(Some(BurnchainTip {
+            block_snapshot,
+            state_transition,
+            received_at: Instant::now()
+        }))
+    }
+
+    fn next_block_timestamp(&mut self, parent_timestamp: u64) -> u64 {
+        match self.config.mocknet.block_interval_range {
+            Some((min_interval, max_interval)) => {
+                let interval = self.block_interval_rng.sample(Uniform::new_inclusive(min_interval, max_interval));
+                parent_timestamp.saturating_add(interval)
+            },
+            None => self.clock.now_secs()
+        }
+    }
+
+    /// Seed of a block's header hash, derived from `mocknet.chain_seed` and a per-block salt.
+    /// Blocks mined by `sync` use an empty salt.
+    pub fn header_seed(chain_seed: &[u8], salt: &[u8]) -> [u8; 32] {
+        let mut preimage = chain_seed.to_vec();
+        preimage.extend_from_slice(salt);
+        Sha256Sum::from_data(&preimage).0
+    }
+
+    /// Header hash of the block mined on top of `parent_hash` with the given `header_seed`.
+    pub fn predict_header_hash(parent_hash: &BurnchainHeaderHash, seed: &[u8; 32]) -> BurnchainHeaderHash {
+        let mut preimage = parent_hash.to_bytes().to_vec();
+        preimage.extend_from_slice(seed);
+        BurnchainHeaderHash(Sha256Sum::from_data(&preimage).0)
+    }
+
+    fn build_next_block_header(&self, parent_height: u64, parent_hash: &BurnchainHeaderHash, salt: &[u8], timestamp: u64) -> BurnchainBlockHeader {
+        let seed = MocknetController::header_seed(&self.config.mocknet.chain_seed, salt);
+        let next_hash = MocknetController::predict_header_hash(parent_hash, &seed);
+
+        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            parent_height + 1,
+            &next_hash, 
+            parent_hash, 
+            &vec![],
+            timestamp));
+        block.header()
+    }
+
+    /// Stamp the operations of the block being mined with `block_header`, in order.
+    fn stamp_operations(payloads: Vec<BlockstackOperationType>, block_header: &BurnchainBlockHeader) -> Vec<BlockstackOperationType> {
+        payloads.into_iter().enumerate()
+            .map(|(i, payload)| MocknetController::stamp_operation(payload, block_header, (i + 1) as u32))
+            .collect()
+    }
+
+    /// Give a submitted operation its on-chain identity in the block being mined.
+    fn stamp_operation(payload: BlockstackOperationType, block_header: &BurnchainBlockHeader, vtxindex: u32) -> BlockstackOperationType {
+        let txid = Txid(Sha256Sum::from_data(format!("{}::{}", block_header.block_height, vtxindex).as_bytes()).0);
+        match payload {
+            BlockstackOperationType::LeaderKeyRegister(payload) => {
+                BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
+                    consensus_hash: payload.consensus_hash,
+                    public_key: payload.public_key,
+                    memo: payload.memo,
+                    address: payload.address,
+                    txid,
+                    vtxindex: vtxindex,
+                    block_height: block_header.block_height,
+                    burn_header_hash: block_header.block_hash,
+                })
+            },
+            BlockstackOperationType::LeaderBlockCommit(payload) => {
+                BlockstackOperationType::LeaderBlockCommit(LeaderBlockCommitOp {
+                    block_header_hash: payload.block_header_hash,
+                    new_seed: payload.new_seed,
+                    parent_block_ptr: payload.parent_block_ptr,
+                    parent_vtxindex: payload.parent_vtxindex,
+                    key_block_ptr: payload.key_block_ptr,
+                    key_vtxindex: payload.key_vtxindex,
+                    memo: payload.memo,
+                    burn_fee: payload.burn_fee,
+                    input: payload.input,
+                    txid,
+                    vtxindex: vtxindex,
+                    block_height: block_header.block_height,
+                    burn_header_hash: block_header.block_hash,
+                })
+            },
+            BlockstackOperationType::UserBurnSupport(payload) => {
+                BlockstackOperationType::UserBurnSupport(UserBurnSupportOp {
+                    address: payload.address,
+                    consensus_hash: payload.consensus_hash,
+                    public_key: payload.public_key,
+                    key_block_ptr: payload.key_block_ptr,
+                    key_vtxindex: payload.key_vtxindex,
+                    block_header_hash_160: payload.block_header_hash_160,
+                    burn_fee: payload.burn_fee,
+                    txid,
+                    vtxindex: vtxindex,
+                    block_height: block_header.block_height,
+                    burn_header_hash: block_header.block_hash,
+                })
+            }
+        }
+    }
+}
+
+impl BurnchainController for MocknetController {
+    
+    fn sortdb_ref(&self) -> &SortitionDB {
+        self.db.as_ref().expect("BUG: did not instantiate burn DB")
+    }
+
+    fn sortdb_mut(&mut self) -> &mut SortitionDB {
+        match self.db {
+            Some(ref mut sortdb) => sortdb,
+            None => {
+                unreachable!();
+            }
+        }
+    }
+    
+    fn get_chain_tip(&mut self) -> BurnchainTip {
+        if self.config.mocknet.disable_tip_cache && self.chain_tip.is_some() {
+            let chain_tip = self.get_chain_tip_with(ConsistencyLevel::Fresh)
+                .expect("FATAL: failed to read the canonical chain tip");
+            self.chain_tip = Some(chain_tip);
+        }
+        match &self.chain_tip {
+            Some(chain_tip) => {
+                chain_tip.clone()
+            },
+            None => {
+                unreachable!();
+            }
+        }
+    }
+   
+    fn start(&mut self) -> BurnchainTip {
+        match self.try_start() {
+            Ok(tip) => tip,
+            Err(e) => panic!("FATAL: failed to start the mocknet burnchain: {:?}", e)
+        }
+    }
+
+    fn submit_operation(&mut self, operation: BlockstackOperationType, op_signer: &mut BurnchainOpSigner) -> bool {
+        match self.try_submit_operation(operation, op_signer) {
+            Ok(()) => true,
+            Err(reason) => {
+                warn!("Rejected submitted operation: {:?}", reason);
+                false
+            }
+        }
+    }
+
+    fn sync(&mut self) -> BurnchainTip {
+        self.mine_queued_ops()
+            .expect("FATAL: failed to mine mocknet block")
     }
 
     #[cfg(test)]
@@ -1,15 +1,20 @@
 pub mod mocknet_controller;
 pub mod bitcoin_regtest_controller;
+pub mod mock_miner;
 
 pub use self::mocknet_controller::{MocknetController};
 pub use self::bitcoin_regtest_controller::{BitcoinRegtestController};
+pub use self::mock_miner::{MockMiner};
 
 use super::operations::BurnchainOpSigner;
 
 use std::time::Instant;
 
 use stacks::burnchains::BurnchainStateTransition;
+use stacks::burnchains::Error as burnchain_error;
+use stacks::util::db::Error as db_error;
 use stacks::chainstate::burn::BlockSnapshot;
+use stacks::chainstate::burn::distribution::BurnSamplePoint;
 use stacks::chainstate::burn::db::sortdb::SortitionDB;
 use stacks::chainstate::burn::operations::BlockstackOperationType;
 
@@ -25,6 +30,28 @@ pub trait BurnchainController {
     fn bootstrap_chain(&mut self, blocks_count: u64);
 }
 
+#[derive(Debug)]
+pub enum BurnchainControllerError {
+    DBError(db_error),
+    BurnchainError(burnchain_error),
+    InvalidReorg(String),
+    InvalidState(String),
+    QueueNotDrained(usize),
+    SortitionOverrideFailed(String),
+}
+
+impl From<db_error> for BurnchainControllerError {
+    fn from(e: db_error) -> BurnchainControllerError {
+        BurnchainControllerError::DBError(e)
+    }
+}
+
+impl From<burnchain_error> for BurnchainControllerError {
+    fn from(e: burnchain_error) -> BurnchainControllerError {
+        BurnchainControllerError::BurnchainError(e)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BurnchainTip {
     pub block_snapshot: BlockSnapshot,
@@ -34,6 +61,10 @@ pub struct BurnchainTip {
 
 impl BurnchainTip {
 
+    /// The burn distribution sortition sampled from, with each candidate's range.
+    pub fn burn_sample_points(&self) -> &[BurnSamplePoint] {
+        &self.state_transition.burn_dist
+    }
 
     pub fn get_winning_tx_index(&self) -> Option<u32> {
 
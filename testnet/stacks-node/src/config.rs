@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::io::{BufReader, Read};
 use std::fs::File;
@@ -8,6 +9,7 @@ use rand::RngCore;
 use stacks::burnchains::{
     MagicBytes, BLOCKSTACK_MAGIC_MAINNET};
 use stacks::burnchains::bitcoin::indexer::FIRST_BLOCK_MAINNET;
+use stacks::chainstate::burn::operations::BlockstackOperationType;
 use stacks::net::connection::ConnectionOptions;
 use stacks::net::{Neighbor, NeighborKey, PeerAddress};
 use stacks::util::secp256k1::Secp256k1PublicKey;
@@ -15,6 +17,7 @@ use stacks::util::hash::{to_hex, hex_bytes};
 use stacks::vm::types::{PrincipalData, QualifiedContractIdentifier, AssetIdentifier} ;
 use stacks::vm::costs::ExecutionCost;
 
+use super::burnchains::mocknet_controller::OpTypeKind;
 use super::node::TESTNET_CHAIN_ID;
 use super::neon_node::TESTNET_PEER_VERSION;
 
@@ -28,6 +31,7 @@ pub struct ConfigFile {
     pub events_observer: Option<Vec<EventObserverConfigFile>>,
     pub connection_options: Option<ConnectionOptionsFile>,
     pub block_limit: Option<BlockLimitFile>,
+    pub mocknet: Option<MocknetConfigFile>,
 }
 
 impl ConfigFile {
@@ -191,6 +195,7 @@ pub struct Config {
     pub events_observers: Vec<EventObserverConfig>,
     pub connection_options: ConnectionOptions,
     pub block_limit: ExecutionCost,
+    pub mocknet: MocknetConfig,
 }
 
 lazy_static! {
@@ -406,13 +411,95 @@ impl Config {
             None => HELIUM_BLOCK_LIMIT.clone()
         };
 
+        let default_mocknet_config = MocknetConfig::default();
+        let mocknet = match config_file.mocknet {
+            Some(mocknet) => MocknetConfig {
+                processing_log_capacity: mocknet.processing_log_capacity.unwrap_or(default_mocknet_config.processing_log_capacity),
+                reward_cycle_length: match mocknet.reward_cycle_length {
+                    Some(0) => panic!("Setting mocknet.reward_cycle_length: must be positive"),
+                    Some(reward_cycle_length) => reward_cycle_length,
+                    None => default_mocknet_config.reward_cycle_length
+                },
+                chain_seed: match mocknet.chain_seed {
+                    Some(seed) => hex_bytes(&seed).expect("Chain seed should be a hex encoded string"),
+                    None => default_mocknet_config.chain_seed
+                },
+                block_interval_range: match mocknet.block_interval_range {
+                    Some((min_interval, max_interval)) if min_interval > max_interval =>
+                        panic!("Setting mocknet.block_interval_range: minimum {} is above maximum {}", min_interval, max_interval),
+                    Some(range) => Some(range),
+                    None => default_mocknet_config.block_interval_range
+                },
+                block_interval_seed: mocknet.block_interval_seed.unwrap_or(default_mocknet_config.block_interval_seed),
+                coinbase_schedule: mocknet.coinbase_schedule.unwrap_or(default_mocknet_config.coinbase_schedule),
+                max_ops_per_block: mocknet.max_ops_per_block.or(default_mocknet_config.max_ops_per_block),
+                chaos: match mocknet.chaos {
+                    Some(chaos) => ChaosConfig {
+                        seed: chaos.seed.unwrap_or(default_mocknet_config.chaos.seed),
+                        inclusion_probability: match chaos.inclusion_probability {
+                            Some(probability) if !(0.0..=1.0).contains(&probability) =>
+                                panic!("Setting mocknet.chaos.inclusion_probability: {} is not between 0 and 1", probability),
+                            Some(probability) => probability,
+                            None => default_mocknet_config.chaos.inclusion_probability
+                        },
+                    },
+                    None => default_mocknet_config.chaos
+                },
+                block_time_secs: mocknet.block_time_secs.or(default_mocknet_config.block_time_secs),
+                reject_zero_seed_commits: mocknet.reject_zero_seed_commits.unwrap_or(default_mocknet_config.reject_zero_seed_commits),
+                key_expiry_blocks: mocknet.key_expiry_blocks.or(default_mocknet_config.key_expiry_blocks),
+                require_signature_for: match mocknet.require_signature_for {
+                    Some(names) => names.iter().map(|name| OpTypeKind::from_name(name)
+                        .unwrap_or_else(|| panic!("Setting mocknet.require_signature_for: unknown operation type `{}`", name)))
+                        .collect(),
+                    None => default_mocknet_config.require_signature_for
+                },
+                disable_tip_cache: mocknet.disable_tip_cache.unwrap_or(default_mocknet_config.disable_tip_cache),
+                batch_window_secs: mocknet.batch_window_secs.unwrap_or(default_mocknet_config.batch_window_secs),
+                genesis_leader_keys: mocknet.genesis_leader_keys.unwrap_or(default_mocknet_config.genesis_leader_keys),
+                reject_equivocations: mocknet.reject_equivocations.unwrap_or(default_mocknet_config.reject_equivocations),
+                op_ordering: match mocknet.op_ordering {
+                    Some(name) => OpOrdering::from_name(&name)
+                        .unwrap_or_else(|| panic!("Setting mocknet.op_ordering: unknown ordering `{}`", name)),
+                    None => default_mocknet_config.op_ordering
+                },
+                ordering_seed: mocknet.ordering_seed.unwrap_or(default_mocknet_config.ordering_seed),
+                min_commits_for_sortition: mocknet.min_commits_for_sortition.unwrap_or(default_mocknet_config.min_commits_for_sortition),
+                dedup_window_blocks: mocknet.dedup_window_blocks.unwrap_or(default_mocknet_config.dedup_window_blocks),
+                requeue_fee_bump_pct: mocknet.requeue_fee_bump_pct.unwrap_or(default_mocknet_config.requeue_fee_bump_pct),
+                max_parent_depth: mocknet.max_parent_depth.or(default_mocknet_config.max_parent_depth),
+                verify_on_resume: mocknet.verify_on_resume.unwrap_or(default_mocknet_config.verify_on_resume),
+                sortition_mode: match mocknet.sortition_mode {
+                    Some(name) => SortitionMode::from_name(&name)
+                        .unwrap_or_else(|| panic!("Setting mocknet.sortition_mode: unknown mode `{}`", name)),
+                    None => default_mocknet_config.sortition_mode
+                },
+                burn_target: mocknet.burn_target.unwrap_or(default_mocknet_config.burn_target),
+                burn_target_window: mocknet.burn_target_window.or(default_mocknet_config.burn_target_window),
+                processing_log_path: mocknet.processing_log_path.or(default_mocknet_config.processing_log_path),
+                stability_depth: mocknet.stability_depth.unwrap_or(default_mocknet_config.stability_depth),
+                reorg_schedule: match mocknet.reorg_schedule {
+                    Some(schedule) => schedule.into_iter()
+                        .map(|reorg| ScheduledReorg {
+                            at_height: reorg.at_height,
+                            depth: reorg.depth,
+                            alt_blocks: vec![vec![]; reorg.alt_blocks as usize],
+                        })
+                        .collect(),
+                    None => default_mocknet_config.reorg_schedule
+                },
+            },
+            None => default_mocknet_config
+        };
+
         Config {
             node,
             burnchain,
             initial_balances,
             events_observers,
             connection_options,
-            block_limit
+            block_limit,
+            mocknet
         }
     }
 
@@ -466,6 +553,7 @@ impl std::default::Default for Config {
             events_observers: vec![],
             connection_options,
             block_limit,
+            mocknet: MocknetConfig::default(),
         }
     }
 }
@@ -550,6 +638,234 @@ pub struct BurnchainConfigFile {
     pub process_exit_at_block_height: Option<u64>,
 }
 
+/// Settings only consulted by the `MocknetController`.
+#[derive(Clone)]
+pub struct MocknetConfig {
+    /// Maximum number of `OpProcessingRecord`s retained; the oldest are dropped first.
+    /// 0 disables the processing log.
+    pub processing_log_capacity: usize,
+    /// Number of burn blocks in a reward cycle; must be positive.
+    pub reward_cycle_length: u64,
+    /// Mixed into every block header hash, so that independently run controllers build distinct chains.
+    pub chain_seed: Vec<u8>,
+    /// When set, each block is timestamped a seeded-random number of seconds in `[min, max]`
+    /// after its parent, instead of with the wall clock.
+    pub block_interval_range: Option<(u64, u64)>,
+    /// Seed for the `block_interval_range` draws.
+    pub block_interval_seed: u64,
+    /// Coinbase emission schedule as `(height, microSTX)` steps sorted by height: each reward
+    /// applies from its height (counted from the first block height) until the next step.
+    pub coinbase_schedule: Vec<(u64, u64)>,
+    /// Most operations `sync` includes in a block; the rest stay queued for later blocks.
+    pub max_ops_per_block: Option<usize>,
+    /// Random disturbances applied to mining.
+    pub chaos: ChaosConfig,
+    /// Expected seconds between blocks, used for time estimates only.
+    pub block_time_secs: Option<u64>,
+    /// Refuse submitted block commits whose `new_seed` is all zeros.
+    pub reject_zero_seed_commits: bool,
+    /// Leader keys registered more than this many blocks before the block a commit
+    /// would be mined in are expired, and commits using them are refused.
+    pub key_expiry_blocks: Option<u64>,
+    /// Operation types that `submit_operation` refuses unless their signer produces signatures.
+    pub require_signature_for: HashSet<OpTypeKind>,
+    /// Make every `get_chain_tip` read the canonical tip from the sortition DB, treating the
+    /// tip cached by the controller as advisory only.
+    pub disable_tip_cache: bool,
+    /// Seconds an operation must have been queued before `sync` includes it in a block,
+    /// measured with the controller's clock; 0 includes operations immediately.
+    pub batch_window_secs: u64,
+    /// Leader keys registered by `start` in a bootstrap block on top of genesis, one per
+    /// `genesis_keychain` index.
+    pub genesis_leader_keys: u64,
+    /// Keep only the first of the block commits that a leader key makes on the same parent
+    /// in one block, instead of just reporting them through `last_equivocations`.
+    pub reject_equivocations: bool,
+    /// How `sync` orders the operations of a block, which decides their vtxindex.
+    pub op_ordering: OpOrdering,
+    /// Breaks ties of `op_ordering` deterministically; independent of `chain_seed`.
+    pub ordering_seed: u64,
+    /// Fewest block commits a block needs to hold a sortition; with fewer, `sync` leaves the
+    /// commits queued and out of the block, which then has no sortition.
+    pub min_commits_for_sortition: usize,
+    /// Besides the queue, `submit_operation` refuses operations identical to one mined in the
+    /// last this many blocks of the canonical fork.
+    pub dedup_window_blocks: u64,
+    /// Percentage by which the burn fee of a block commit is raised each time a reorg re-queues it;
+    /// 0 re-queues commits unchanged.
+    pub requeue_fee_bump_pct: u64,
+    /// Block commits whose parent is more than this many blocks below the chain tip are refused;
+    /// unlimited when unset.
+    pub max_parent_depth: Option<u64>,
+    /// Make `start` re-verify the header and sortition hash chains of an existing DB before
+    /// returning its tip; `try_start` returns any inconsistency as an error, `start` panics on it.
+    pub verify_on_resume: bool,
+    /// How `sync` picks the winner of a block with commits.
+    pub sortition_mode: SortitionMode,
+    /// Value of `next_burn_target` when `burn_target_window` is unset, or before any block is mined.
+    pub burn_target: u64,
+    /// When set, `next_burn_target` is the mean amount burned per block over this many most recent
+    /// blocks of the canonical fork.
+    pub burn_target_window: Option<u64>,
+    /// File that `sync` appends the `OpProcessingRecord`s of every mined block to, one JSON object
+    /// per line, independently of `processing_log_capacity`.
+    pub processing_log_path: Option<String>,
+    /// Blocks this many below the chain tip are final; the controller reports each one to the
+    /// finalization observer once per fork it is final on.
+    pub stability_depth: u64,
+    /// Reorgs that `sync` performs on its own, each right after it mines the block at its `at_height`.
+    pub reorg_schedule: Vec<ScheduledReorg>,
+}
+
+impl MocknetConfig {
+    fn default() -> MocknetConfig {
+        MocknetConfig {
+            processing_log_capacity: 4096,
+            reward_cycle_length: 20,
+            chain_seed: vec![],
+            block_interval_range: None,
+            block_interval_seed: 0,
+            coinbase_schedule: vec![(0, 500 * 1_000_000), (262_980, 400 * 1_000_000), (525_960, 300 * 1_000_000)],
+            max_ops_per_block: None,
+            chaos: ChaosConfig::default(),
+            block_time_secs: None,
+            reject_zero_seed_commits: true,
+            key_expiry_blocks: None,
+            require_signature_for: HashSet::new(),
+            disable_tip_cache: false,
+            batch_window_secs: 0,
+            genesis_leader_keys: 0,
+            reject_equivocations: false,
+            op_ordering: OpOrdering::Fifo,
+            ordering_seed: 0,
+            min_commits_for_sortition: 1,
+            dedup_window_blocks: 0,
+            requeue_fee_bump_pct: 0,
+            max_parent_depth: None,
+            verify_on_resume: false,
+            sortition_mode: SortitionMode::BurnWeighted,
+            burn_target: 0,
+            burn_target_window: None,
+            processing_log_path: None,
+            stability_depth: 6,
+            reorg_schedule: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct MocknetConfigFile {
+    pub processing_log_capacity: Option<usize>,
+    pub reward_cycle_length: Option<u64>,
+    pub chain_seed: Option<String>,
+    pub block_interval_range: Option<(u64, u64)>,
+    pub block_interval_seed: Option<u64>,
+    pub coinbase_schedule: Option<Vec<(u64, u64)>>,
+    pub max_ops_per_block: Option<usize>,
+    pub chaos: Option<ChaosConfigFile>,
+    pub block_time_secs: Option<u64>,
+    pub reject_zero_seed_commits: Option<bool>,
+    pub key_expiry_blocks: Option<u64>,
+    pub require_signature_for: Option<Vec<String>>,
+    pub disable_tip_cache: Option<bool>,
+    pub batch_window_secs: Option<u64>,
+    pub genesis_leader_keys: Option<u64>,
+    pub reject_equivocations: Option<bool>,
+    pub op_ordering: Option<String>,
+    pub ordering_seed: Option<u64>,
+    pub min_commits_for_sortition: Option<usize>,
+    pub dedup_window_blocks: Option<u64>,
+    pub requeue_fee_bump_pct: Option<u64>,
+    pub max_parent_depth: Option<u64>,
+    pub verify_on_resume: Option<bool>,
+    pub sortition_mode: Option<String>,
+    pub burn_target: Option<u64>,
+    pub burn_target_window: Option<u64>,
+    pub processing_log_path: Option<String>,
+    pub stability_depth: Option<u64>,
+    pub reorg_schedule: Option<Vec<ScheduledReorgFile>>,
+}
+
+/// Order of the operations within a mocknet block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpOrdering {
+    /// Submission order
+    Fifo,
+    /// Highest burn fee first; operations burning as much are ordered by `ordering_seed`
+    BurnFee,
+}
+
+impl OpOrdering {
+    pub fn from_name(name: &str) -> Option<OpOrdering> {
+        match name {
+            "fifo" => Some(OpOrdering::Fifo),
+            "burn_fee" => Some(OpOrdering::BurnFee),
+            _ => None
+        }
+    }
+}
+
+/// How the mocknet picks the sortition winner of a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortitionMode {
+    /// Consensus rules: the sortition hash picks a commit weighted by burn fee
+    BurnWeighted,
+    /// The miners committing in a block win in turn, whatever they burn
+    RoundRobin,
+}
+
+impl SortitionMode {
+    pub fn from_name(name: &str) -> Option<SortitionMode> {
+        match name {
+            "burn_weighted" => Some(SortitionMode::BurnWeighted),
+            "round_robin" => Some(SortitionMode::RoundRobin),
+            _ => None
+        }
+    }
+}
+
+/// A reorg performed by `sync` once it mines the block at `at_height`: the last `depth` blocks
+/// are replaced with `alt_blocks`, as with `MocknetController::reorg`.
+#[derive(Clone)]
+pub struct ScheduledReorg {
+    pub at_height: u64,
+    pub depth: u64,
+    pub alt_blocks: Vec<Vec<BlockstackOperationType>>,
+}
+
+/// A `ScheduledReorg` as read from the config file, which replaces blocks with
+/// `alt_blocks` empty blocks.
+#[derive(Clone, Deserialize)]
+pub struct ScheduledReorgFile {
+    pub at_height: u64,
+    pub depth: u64,
+    pub alt_blocks: u64,
+}
+
+/// Seeded misbehaviour of the mocknet, for testing how nodes cope with an unreliable burnchain.
+#[derive(Clone)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Probability in `[0, 1]` that a queued operation makes it into the next block;
+    /// operations left out stay queued.
+    pub inclusion_probability: f64,
+}
+
+impl ChaosConfig {
+    fn default() -> ChaosConfig {
+        ChaosConfig {
+            seed: 0,
+            inclusion_probability: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Default)]
+pub struct ChaosConfigFile {
+    pub seed: Option<u64>,
+    pub inclusion_probability: Option<f64>,
+}
+
 #[derive(Clone, Default)]
 pub struct NodeConfig {
     pub name: String,
@@ -188,7 +188,9 @@ impl Keychain {
         }
     }
 
+    /// An op signer over every key of the keychain, with its threshold, matching `get_burnchain_signer`.
     pub fn generate_op_signer(&self) -> BurnchainOpSigner {
-        BurnchainOpSigner::new(self.secret_keys[0], false)
+        let public_keys = self.secret_keys.iter().map(|ref pk| StacksPublicKey::from_private(pk)).collect();
+        BurnchainOpSigner::new_multisig(public_keys, self.secret_keys.clone(), self.threshold as usize, false)
     }
 }
@@ -1,8 +1,11 @@
 use stacks::burnchains::{PrivateKey};
 use stacks::util::secp256k1::{MessageSignature, Secp256k1PublicKey, Secp256k1PrivateKey};
 
+#[derive(Clone)]
 pub struct BurnchainOpSigner {
-    secret_key: Secp256k1PrivateKey,
+    secret_keys: Vec<Secp256k1PrivateKey>,
+    public_keys: Vec<Secp256k1PublicKey>,
+    num_sigs: usize,
     is_one_off: bool,
     is_disposed: bool,
     usages: u8,
@@ -12,16 +15,39 @@ impl BurnchainOpSigner {
 
     pub fn new(secret_key: Secp256k1PrivateKey, is_one_off: bool) -> BurnchainOpSigner {
         BurnchainOpSigner {
-            secret_key: secret_key,
+            public_keys: vec![Secp256k1PublicKey::from_private(&secret_key)],
+            secret_keys: vec![secret_key],
+            num_sigs: 1,
             usages: 0,
             is_one_off,
             is_disposed: false,
         }
     }
 
-    pub fn get_public_key(&mut self) -> Secp256k1PublicKey {
-        let public_key = Secp256k1PublicKey::from_private(&self.secret_key);
-        public_key
+    /// An M-of-N signer over `public_keys`, where `num_sigs` is M.
+    /// `secret_keys` are the keys this signer holds, which may be fewer than M.
+    pub fn new_multisig(public_keys: Vec<Secp256k1PublicKey>, secret_keys: Vec<Secp256k1PrivateKey>, num_sigs: usize, is_one_off: bool) -> BurnchainOpSigner {
+        BurnchainOpSigner {
+            secret_keys,
+            public_keys,
+            num_sigs,
+            usages: 0,
+            is_one_off,
+            is_disposed: false,
+        }
+    }
+
+    /// The first public key of the signer, or `None` if it has no keys.
+    pub fn get_public_key(&mut self) -> Option<Secp256k1PublicKey> {
+        self.public_keys.first().cloned()
+    }
+
+    pub fn get_public_keys(&self) -> &[Secp256k1PublicKey] {
+        &self.public_keys
+    }
+
+    pub fn num_sigs_required(&self) -> usize {
+        self.num_sigs
     }
 
     pub fn sign_message(&mut self, hash: &[u8]) -> Option<MessageSignature> {
@@ -29,17 +55,36 @@ impl BurnchainOpSigner {
             return None;
         }
 
-        let signature = match self.secret_key.sign(hash) {
+        let signature = match self.secret_keys.first()?.sign(hash) {
             Ok(r) => r,
             _ => return None
         };
-        self.usages += 1;
-        
+        self.record_usage();
+
+        Some(signature)
+    }
+
+    /// Sign with every key this signer holds.
+    /// Counts as a single usage, and yields no signatures once disposed.
+    pub fn sign_message_multisig(&mut self, hash: &[u8]) -> Vec<MessageSignature> {
+        if self.is_disposed {
+            return vec![];
+        }
+
+        let signatures: Vec<_> = self.secret_keys.iter()
+            .filter_map(|secret_key| secret_key.sign(hash).ok())
+            .collect();
+        self.record_usage();
+
+        signatures
+    }
+
+    fn record_usage(&mut self) {
+        self.usages = self.usages.saturating_add(1);
+
         if self.is_one_off && self.usages == 1 {
             self.is_disposed = true;
         }
-
-        Some(signature)
     }
 
     pub fn dispose(&mut self) {
@@ -86,7 +86,7 @@ impl RunLoop {
             info!("Miner node: checking UTXOs at address: {}", btc_addr);
 
             let utxos = burnchain.get_utxos(
-                &keychain.generate_op_signer().get_public_key(), 1);
+                &keychain.generate_op_signer().get_public_key().expect("FATAL: keychain has no public key"), 1);
             if utxos.is_none() {
                 error!("Miner node: UTXOs not found. Switching to Follower node. Restart node when you get some UTXOs.");
                 false
@@ -0,0 +1,2477 @@
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use stacks::address::AddressHashMode;
+use stacks::burnchains::{Burnchain, Txid, BurnchainHeaderHash};
+use stacks::chainstate::burn::{BlockHeaderHash, ConsensusHash, VRFSeed};
+use stacks::chainstate::burn::db::sortdb::SortitionDB;
+use stacks::chainstate::stacks::StacksAddress;
+use stacks::chainstate::burn::operations::{
+    BlockstackOperationType,
+    LeaderBlockCommitOp,
+    LeaderKeyRegisterOp,
+    UserBurnSupportOp,
+};
+use stacks::util::hash::{Hash160, Sha256Sum};
+use stacks::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+use stacks::util::uint::{BitArray, Uint256};
+use stacks::util::vrf::VRFPublicKey;
+
+use crate::{Config, Keychain, MocknetController, BurnchainController, BurnchainTip};
+use crate::config::{OpOrdering, ScheduledReorg, SortitionMode};
+use crate::operations::BurnchainOpSigner;
+use crate::burnchains::{BurnchainControllerError, MockMiner};
+use crate::burnchains::mocknet_controller::{ChainValidationError, ConsistencyLevel, ControllerEvent, MockClock, OpProcessingRecord, OpSink, OpTypeKind, RejectReason, ReorgWatch, SubmitDecision, ValidationRule};
+
+fn make_mocknet_controller(conf: Config) -> MocknetController {
+    let mut controller = MocknetController::new(conf);
+    controller.start();
+    controller
+}
+
+fn make_keychain(seed: u8) -> Keychain {
+    Keychain::default(vec![seed; 32])
+}
+
+fn make_leader_key_register(keychain: &mut Keychain, burnchain_tip: &BurnchainTip) -> BlockstackOperationType {
+    let vrf_public_key = keychain.rotate_vrf_keypair(burnchain_tip.block_snapshot.block_height);
+    BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
+        public_key: vrf_public_key,
+        memo: vec![],
+        address: keychain.get_address(),
+        consensus_hash: burnchain_tip.block_snapshot.consensus_hash.clone(),
+        vtxindex: 0,
+        txid: Txid([0u8; 32]),
+        block_height: 0,
+        burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+    })
+}
+
+fn make_block_commit(keychain: &Keychain, key: &LeaderKeyRegisterOp, block_header_hash: BlockHeaderHash, burn_fee: u64) -> BlockstackOperationType {
+    BlockstackOperationType::LeaderBlockCommit(LeaderBlockCommitOp {
+        block_header_hash,
+        new_seed: VRFSeed(block_header_hash.0),
+        parent_block_ptr: 0,
+        parent_vtxindex: 0,
+        key_block_ptr: key.block_height as u32,
+        key_vtxindex: key.vtxindex as u16,
+        memo: vec![],
+        burn_fee,
+        input: keychain.get_burnchain_signer(),
+        vtxindex: 0,
+        txid: Txid([0u8; 32]),
+        block_height: 0,
+        burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+    })
+}
+
+/// Find the mined leader key registration for `public_key` in the ops accepted at `tip`.
+fn find_leader_key(tip: &BurnchainTip, public_key: &VRFPublicKey) -> LeaderKeyRegisterOp {
+    tip.state_transition.accepted_ops.iter()
+        .filter_map(|op| match op {
+            BlockstackOperationType::LeaderKeyRegister(key) if &key.public_key == public_key => Some(key.clone()),
+            _ => None
+        })
+        .next()
+        .expect("leader key was not mined")
+}
+
+fn leader_key_public_key(op: &BlockstackOperationType) -> VRFPublicKey {
+    match op {
+        BlockstackOperationType::LeaderKeyRegister(key) => key.public_key.clone(),
+        _ => panic!("not a leader key register")
+    }
+}
+
+/// A scripted mocknet run: the config to start from and the steps driving the controller.
+pub struct Scenario {
+    pub config: Config,
+    pub steps: Box<dyn Fn(&mut MocknetController)>,
+}
+
+/// Height, header hash, consensus hash and serialized operations of every block on the
+/// canonical fork.  Neither hash depends on what the operations contain, hence the operations.
+fn run_scenario(scenario: &Scenario) -> Vec<(u64, BurnchainHeaderHash, ConsensusHash, String)> {
+    let mut conf = scenario.config.clone();
+    conf.node.working_dir = Config::default().node.working_dir;
+    let mut controller = make_mocknet_controller(conf);
+    (scenario.steps)(&mut controller);
+
+    let tip = controller.get_chain_tip().block_snapshot;
+    let ic = controller.sortdb_ref().index_conn();
+    let conn = controller.sortdb_ref().conn();
+    (0..(tip.block_height + 1))
+        .map(|height| {
+            let snapshot = SortitionDB::get_ancestor_snapshot(&ic, height, &tip.sortition_id).unwrap().unwrap();
+            let ops = serde_json::to_string(&(
+                SortitionDB::get_leader_keys_by_block(conn, &snapshot.sortition_id).unwrap(),
+                SortitionDB::get_block_commits_by_block(conn, &snapshot.sortition_id).unwrap(),
+                SortitionDB::get_user_burns_by_block(conn, &snapshot.sortition_id).unwrap())).unwrap();
+            (snapshot.block_height, snapshot.burn_header_hash, snapshot.consensus_hash, ops)
+        })
+        .collect()
+}
+
+/// Run `scenario` once for reference, then `runs` more times from scratch,
+/// reporting the first run and height that differ from the reference.
+pub fn check_deterministic(scenario: &Scenario, runs: usize) -> Result<(), String> {
+    let reference = run_scenario(scenario);
+    for run in 1..(runs + 1) {
+        let result = run_scenario(scenario);
+        if result == reference {
+            continue;
+        }
+        let divergence = reference.iter().zip(result.iter())
+            .find(|(expected, actual)| expected != actual)
+            .map(|(expected, _)| expected.0)
+            .unwrap_or(cmp::min(reference.len(), result.len()) as u64);
+        return Err(format!("run {} diverged from the reference run at height {}", run, divergence));
+    }
+    Ok(())
+}
+
+pub fn assert_deterministic(scenario: &Scenario, runs: usize) {
+    if let Err(e) = check_deterministic(scenario, runs) {
+        panic!("Scenario is not deterministic: {}", e);
+    }
+}
+
+#[test]
+fn mocknet_processing_log_keeps_most_recent_records() {
+    let mut conf = Config::default();
+    conf.mocknet.processing_log_capacity = 3;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(1);
+    let mut op_signer = keychain.generate_op_signer();
+
+    for _ in 0..3 {
+        let tip = controller.get_chain_tip();
+        for _ in 0..2 {
+            let op = make_leader_key_register(&mut keychain, &tip);
+            assert!(controller.submit_operation(op, &mut op_signer));
+        }
+        controller.sync();
+    }
+
+    let retained: Vec<_> = controller.processing_log().iter()
+        .map(|record| (record.block_height, record.vtxindex))
+        .collect();
+    assert_eq!(retained, vec![(2, 2), (3, 1), (3, 2)]);
+    assert!(controller.processing_log().iter().all(|record| record.accepted));
+}
+
+#[test]
+fn mocknet_processing_log_disabled() {
+    let mut conf = Config::default();
+    conf.mocknet.processing_log_capacity = 0;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(1);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller.submit_operation(op, &mut op_signer);
+    controller.sync();
+
+    assert!(controller.processing_log().is_empty());
+}
+
+#[test]
+fn mocknet_seed_queue_from_another_controller() {
+    let mut controller_a = make_mocknet_controller(Config::default());
+    let mut controller_b = make_mocknet_controller(Config::default());
+    controller_a.sync();
+    controller_b.sync();
+
+    let mut keychain = make_keychain(2);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let tip = controller_a.get_chain_tip();
+    for _ in 0..2 {
+        let op = make_leader_key_register(&mut keychain, &tip);
+        controller_a.submit_operation(op, &mut op_signer);
+    }
+
+    let leftover = controller_a.take_queue();
+    assert_eq!(leftover.len(), 2);
+    assert!(controller_a.take_queue().is_empty());
+
+    controller_b.seed_queue(leftover);
+    let tip = controller_b.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 2);
+}
+
+#[test]
+fn mocknet_memo_validator_requires_magic_prefix() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.set_memo_validator(OpTypeKind::LeaderKeyRegister,
+                                  Box::new(|memo: &[u8]| memo.starts_with(b"X2")));
+
+    let mut keychain = make_keychain(3);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+
+    let mut rejected = make_leader_key_register(&mut keychain, &tip);
+    if let BlockstackOperationType::LeaderKeyRegister(ref mut op) = rejected {
+        op.memo = b"nope".to_vec();
+    }
+    assert_eq!(controller.try_submit_operation(rejected, &mut op_signer), Err(RejectReason::InvalidMemo));
+
+    let mut accepted = make_leader_key_register(&mut keychain, &tip);
+    if let BlockstackOperationType::LeaderKeyRegister(ref mut op) = accepted {
+        op.memo = b"X2payload".to_vec();
+    }
+    assert_eq!(controller.try_submit_operation(accepted, &mut op_signer), Ok(()));
+    assert_eq!(controller.take_queue().len(), 1);
+}
+
+#[test]
+fn mocknet_reorg_requeues_orphaned_ops() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+
+    let mut keychain = make_keychain(4);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller.submit_operation(op, &mut op_signer);
+    let mined_tip = controller.sync();
+    let mined_txid = mined_tip.state_transition.accepted_ops[0].txid();
+
+    let new_tip = controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    assert_eq!(new_tip.block_snapshot.block_height, 3);
+    assert!(new_tip.block_snapshot.burn_header_hash != mined_tip.block_snapshot.burn_header_hash);
+    assert_eq!(controller.last_orphaned_ops(), &[mined_txid][..]);
+
+    let requeued = controller.take_queue();
+    assert_eq!(requeued.len(), 1);
+    assert_eq!(requeued[0].txid(), mined_txid);
+
+    controller.seed_queue(requeued);
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+}
+
+#[test]
+fn mocknet_sortition_winner_pubkey() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+
+    let mut miners = vec![make_keychain(5), make_keychain(6)];
+
+    for round in 0..3u8 {
+        let tip = controller.get_chain_tip();
+        let mut public_keys = vec![];
+        for miner in miners.iter_mut() {
+            let op = make_leader_key_register(miner, &tip);
+            public_keys.push(leader_key_public_key(&op));
+            controller.submit_operation(op, &mut miner.generate_op_signer());
+        }
+        let key_tip = controller.sync();
+
+        let mut commit_keys = vec![];
+        for (i, miner) in miners.iter().enumerate() {
+            let key = find_leader_key(&key_tip, &public_keys[i]);
+            let commit = make_block_commit(miner, &key, BlockHeaderHash([round * 2 + i as u8 + 1; 32]), 1000 * (i as u64 + 1));
+            controller.submit_operation(commit, &mut miner.generate_op_signer());
+            commit_keys.push(key.public_key);
+        }
+        let commit_tip = controller.sync();
+        assert!(commit_tip.block_snapshot.sortition);
+
+        let winner_index = commit_tip.state_transition.accepted_ops.iter()
+            .filter_map(|op| match op {
+                BlockstackOperationType::LeaderBlockCommit(commit) => Some(commit),
+                _ => None
+            })
+            .position(|commit| commit.txid == commit_tip.block_snapshot.winning_block_txid)
+            .expect("no winning commit");
+
+        let winner_pubkey = controller.sortition_winner_pubkey(commit_tip.block_snapshot.block_height).unwrap();
+        assert_eq!(winner_pubkey, Some(commit_keys[winner_index].clone()));
+        assert_eq!(controller.sortition_winner_pubkey(key_tip.block_snapshot.block_height).unwrap(), None);
+    }
+}
+
+#[test]
+fn mocknet_mock_miner_auto_renews_keys() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+
+    let mut miner = MockMiner::new(make_keychain(7), 1000);
+    miner.auto_renew_keys = true;
+
+    // First round only registers the initial key
+    miner.simulate_round(&mut controller);
+    assert_eq!(miner.available_keys().len(), 1);
+
+    for _ in 0..5 {
+        let tip = miner.simulate_round(&mut controller);
+        assert!(tip.block_snapshot.sortition);
+        assert!(tip.get_winning_tx_index().is_some());
+        assert_eq!(tip.state_transition.consumed_leader_keys.len(), 1);
+        assert_eq!(miner.available_keys().len(), 1);
+    }
+}
+
+#[test]
+fn mocknet_mock_miner_without_renewal_runs_out_of_keys() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+
+    let mut miner = MockMiner::new(make_keychain(8), 1000);
+    miner.register_key(&mut controller);
+    miner.simulate_round(&mut controller);
+    assert_eq!(miner.available_keys().len(), 1);
+
+    let tip = miner.simulate_round(&mut controller);
+    assert!(tip.get_winning_tx_index().is_some());
+    assert!(miner.available_keys().is_empty());
+
+    let tip = miner.simulate_round(&mut controller);
+    assert!(tip.state_transition.accepted_ops.is_empty());
+}
+
+#[test]
+fn mocknet_reward_cycle_of_height() {
+    let mut conf = Config::default();
+    conf.mocknet.reward_cycle_length = 5;
+    let controller = MocknetController::new(conf);
+
+    assert_eq!(controller.reward_cycle_of_height(0), 0);
+    assert_eq!(controller.reward_cycle_of_height(4), 0);
+    assert_eq!(controller.reward_cycle_of_height(5), 1);
+    assert_eq!(controller.reward_cycle_of_height(9), 1);
+    assert_eq!(controller.reward_cycle_of_height(10), 2);
+    assert_eq!(controller.reward_cycle_of_height(123), 24);
+}
+
+struct RecordingSink {
+    mined: Arc<Mutex<Vec<(u64, Txid)>>>,
+}
+
+impl OpSink for RecordingSink {
+    fn on_ops_mined(&mut self, height: u64, ops: &[BlockstackOperationType]) {
+        let mut mined = self.mined.lock().unwrap();
+        for op in ops.iter() {
+            mined.push((height, op.txid()));
+        }
+    }
+}
+
+#[test]
+fn mocknet_op_sinks_receive_every_mined_op() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mined_a = Arc::new(Mutex::new(vec![]));
+    let mined_b = Arc::new(Mutex::new(vec![]));
+    controller.add_op_sink(Box::new(RecordingSink { mined: mined_a.clone() }));
+    controller.add_op_sink(Box::new(RecordingSink { mined: mined_b.clone() }));
+
+    let mut keychain = make_keychain(9);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let mut expected = vec![];
+    for _ in 0..3 {
+        let tip = controller.get_chain_tip();
+        for _ in 0..2 {
+            let op = make_leader_key_register(&mut keychain, &tip);
+            controller.submit_operation(op, &mut op_signer);
+        }
+        let tip = controller.sync();
+        for op in tip.state_transition.accepted_ops.iter() {
+            expected.push((tip.block_snapshot.block_height, op.txid()));
+        }
+    }
+
+    assert_eq!(expected.len(), 6);
+    assert_eq!(*mined_a.lock().unwrap(), expected);
+    assert_eq!(*mined_b.lock().unwrap(), expected);
+}
+
+#[test]
+fn mocknet_heal_from_partition_converges() {
+    let mut conf_a = Config::default();
+    conf_a.mocknet.chain_seed = vec![0xa];
+    let mut conf_b = Config::default();
+    conf_b.mocknet.chain_seed = vec![0xb];
+    let mut controller_a = make_mocknet_controller(conf_a);
+    let mut controller_b = make_mocknet_controller(conf_b);
+
+    let mut keychain = make_keychain(10);
+    let mut op_signer = keychain.generate_op_signer();
+
+    // Partition: A mines 3 blocks, B mines 2 blocks, the first one with a key register.
+    for _ in 0..3 {
+        controller_a.sync();
+    }
+    let tip = controller_b.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller_b.submit_operation(op, &mut op_signer);
+    let orphaned_tip = controller_b.sync();
+    let orphaned_txid = orphaned_tip.state_transition.accepted_ops[0].txid();
+    controller_b.sync();
+
+    let tip_a = controller_a.heal_from(&controller_b).unwrap();
+    let tip_b = controller_b.heal_from(&controller_a).unwrap();
+
+    assert_eq!(tip_a.block_snapshot.block_height, 3);
+    assert_eq!(tip_a.block_snapshot.burn_header_hash, tip_b.block_snapshot.burn_header_hash);
+    assert_eq!(controller_a.get_chain_tip().block_snapshot.burn_header_hash,
+               controller_b.get_chain_tip().block_snapshot.burn_header_hash);
+
+    // B's key register was on the losing branch and is waiting to be mined again.
+    assert_eq!(controller_b.last_orphaned_ops(), &[orphaned_txid][..]);
+    assert_eq!(controller_b.take_queue().len(), 1);
+}
+
+#[test]
+fn mocknet_heal_from_detects_same_hash_different_blocks() {
+    // same chain seed: both partitions mine blocks under the same header hashes
+    let mut controller_a = make_mocknet_controller(Config::default());
+    let mut controller_b = make_mocknet_controller(Config::default());
+
+    let mut keychain = make_keychain(11);
+    let mut op_signer = keychain.generate_op_signer();
+    controller_a.sync();
+    let tip = controller_b.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller_b.submit_operation(op, &mut op_signer);
+    controller_b.sync();
+    assert_eq!(controller_a.get_chain_tip().block_snapshot.burn_header_hash,
+               controller_b.get_chain_tip().block_snapshot.burn_header_hash);
+
+    match controller_a.heal_from(&controller_b) {
+        Err(BurnchainControllerError::InvalidState(_)) => {},
+        other => panic!("expected diverging blocks to be detected, got {:?}", other.map(|tip| tip.block_snapshot.block_height))
+    }
+
+    // blocks mined identically on both sides are fine
+    let mut controller_c = make_mocknet_controller(Config::default());
+    controller_c.sync();
+    controller_c.sync();
+    let mut controller_d = make_mocknet_controller(Config::default());
+    controller_d.sync();
+    let healed = controller_d.heal_from(&controller_c).unwrap();
+    assert_eq!(healed.block_snapshot.burn_header_hash, controller_c.get_chain_tip().block_snapshot.burn_header_hash);
+}
+
+fn key_register_scenario(keychain_seed: fn() -> Vec<u8>) -> Scenario {
+    Scenario {
+        config: Config::default(),
+        steps: Box::new(move |controller: &mut MocknetController| {
+            let mut keychain = Keychain::default(keychain_seed());
+            let mut op_signer = keychain.generate_op_signer();
+            controller.sync();
+            for _ in 0..3 {
+                let tip = controller.get_chain_tip();
+                let op = make_leader_key_register(&mut keychain, &tip);
+                controller.submit_operation(op, &mut op_signer);
+                controller.sync();
+            }
+        })
+    }
+}
+
+#[test]
+fn mocknet_assert_deterministic_passes_for_fixed_seeds() {
+    let scenario = key_register_scenario(|| vec![11u8; 32]);
+    assert_deterministic(&scenario, 3);
+}
+
+#[test]
+fn mocknet_assert_deterministic_detects_os_randomness() {
+    let scenario = key_register_scenario(|| {
+        let mut seed = vec![0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        seed
+    });
+    let err = check_deterministic(&scenario, 2).unwrap_err();
+    assert!(err.starts_with("run 1 diverged"), "{}", err);
+    assert!(err.ends_with("at height 2"), "{}", err);
+}
+
+#[test]
+fn mocknet_compact_export_supports_mining_and_shallow_reorgs() {
+    let mut controller = make_mocknet_controller(Config::default());
+    for _ in 0..3 {
+        controller.sync();
+    }
+    // Orphans the block at height 3
+    controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    for _ in 0..6 {
+        controller.sync();
+    }
+    let tip = controller.get_chain_tip();
+    assert_eq!(tip.block_snapshot.block_height, 10);
+
+    let full: serde_json::Value = serde_json::from_str(&controller.export_state(None).unwrap()).unwrap();
+    let compact_state = controller.export_state(Some(2)).unwrap();
+    let compact: serde_json::Value = serde_json::from_str(&compact_state).unwrap();
+    assert_eq!(full["blocks"].as_array().unwrap().len(), 11);
+    assert_eq!(compact["blocks"].as_array().unwrap().len(), 10);
+
+    let mut imported = MocknetController::import_state(Config::default(), &compact_state).unwrap();
+    assert_eq!(imported.get_chain_tip().block_snapshot.burn_header_hash, tip.block_snapshot.burn_header_hash);
+    assert_eq!(imported.get_chain_tip().block_snapshot.consensus_hash, tip.block_snapshot.consensus_hash);
+
+    let next_tip = imported.sync();
+    assert_eq!(next_tip.block_snapshot.block_height, 11);
+
+    let reorged_tip = imported.reorg(2, vec![vec![], vec![], vec![]]).unwrap();
+    assert_eq!(reorged_tip.block_snapshot.block_height, 12);
+}
+
+#[test]
+fn mocknet_block_validator_caps_total_burn() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.set_block_validator(Box::new(|ops: &[BlockstackOperationType]| {
+        let total_burn: u64 = ops.iter()
+            .map(|op| match op {
+                BlockstackOperationType::LeaderBlockCommit(op) => op.burn_fee,
+                BlockstackOperationType::UserBurnSupport(op) => op.burn_fee,
+                BlockstackOperationType::LeaderKeyRegister(_) => 0
+            })
+            .sum();
+        if total_burn > 3000 {
+            Err(format!("total burn {} exceeds the cap", total_burn))
+        } else {
+            Ok(())
+        }
+    }));
+
+    let mut keychain = make_keychain(12);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key_tip = controller.sync();
+    let key = find_leader_key(&key_tip, &public_key);
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 5000);
+    controller.submit_operation(commit, &mut op_signer);
+    let tip = controller.sync();
+    assert_eq!(tip.block_snapshot.burn_header_hash, key_tip.block_snapshot.burn_header_hash);
+    assert_eq!(controller.take_queue().len(), 1);
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([2u8; 32]), 2000);
+    controller.submit_operation(commit, &mut op_signer);
+    let tip = controller.sync();
+    assert_eq!(tip.block_snapshot.block_height, key_tip.block_snapshot.block_height + 1);
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+}
+
+#[test]
+fn mocknet_parent_snapshot() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let genesis = controller.get_chain_tip().block_snapshot;
+    assert!(controller.parent_snapshot(&genesis).unwrap().is_none());
+
+    let mut snapshots = vec![genesis];
+    for _ in 0..3 {
+        snapshots.push(controller.sync().block_snapshot);
+    }
+
+    for height in 1..snapshots.len() {
+        let parent = controller.parent_snapshot(&snapshots[height]).unwrap().unwrap();
+        assert_eq!(parent.block_height, height as u64 - 1);
+        assert_eq!(parent.burn_header_hash, snapshots[height - 1].burn_header_hash);
+    }
+}
+
+fn block_intervals(seed: u64) -> Vec<u64> {
+    let mut conf = Config::default();
+    conf.mocknet.block_interval_range = Some((5, 15));
+    conf.mocknet.block_interval_seed = seed;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut last_timestamp = controller.get_chain_tip().block_snapshot.burn_header_timestamp;
+    let mut intervals = vec![];
+    for _ in 0..10 {
+        let timestamp = controller.sync().block_snapshot.burn_header_timestamp;
+        intervals.push(timestamp - last_timestamp);
+        last_timestamp = timestamp;
+    }
+    intervals
+}
+
+#[test]
+fn mocknet_block_interval_range_is_seeded() {
+    let intervals = block_intervals(42);
+    assert_eq!(intervals, block_intervals(42));
+    assert!(intervals.iter().all(|interval| *interval >= 5 && *interval <= 15));
+    assert!(intervals != block_intervals(43));
+}
+
+/// Fault injector: overwrite the stored parent hash of the canonical block at `height`.
+fn corrupt_parent_hash(controller: &mut MocknetController, height: u64) {
+    let tip = controller.get_chain_tip().block_snapshot;
+    let ic = controller.sortdb_ref().index_conn();
+    let snapshot = SortitionDB::get_ancestor_snapshot(&ic, height, &tip.sortition_id).unwrap().unwrap();
+    let sql = format!("UPDATE snapshots SET parent_burn_header_hash = '{}' WHERE sortition_id = '{}'",
+                      BurnchainHeaderHash([0xff; 32]), snapshot.sortition_id);
+    controller.sortdb_ref().conn().execute_batch(&sql).unwrap();
+}
+
+#[test]
+fn mocknet_verify_header_chain() {
+    let mut controller = make_mocknet_controller(Config::default());
+    for _ in 0..5 {
+        controller.sync();
+    }
+    assert!(controller.verify_header_chain().unwrap());
+    assert_eq!(controller.find_header_chain_break().unwrap(), None);
+
+    corrupt_parent_hash(&mut controller, 3);
+    assert!(!controller.verify_header_chain().unwrap());
+    assert_eq!(controller.find_header_chain_break().unwrap(), Some(3));
+}
+
+#[test]
+fn mocknet_coinbase_reward_at_height() {
+    let default_controller = MocknetController::new(Config::default());
+    assert_eq!(default_controller.coinbase_reward_at_height(1), 500 * 1_000_000);
+    assert_eq!(default_controller.coinbase_reward_at_height(262_980), 400 * 1_000_000);
+
+    let mut conf = Config::default();
+    conf.mocknet.coinbase_schedule = vec![(0, 1000), (10, 500), (20, 250)];
+    let controller = MocknetController::new(conf);
+
+    assert_eq!(controller.coinbase_reward_at_height(0), 1000);
+    assert_eq!(controller.coinbase_reward_at_height(9), 1000);
+    assert_eq!(controller.coinbase_reward_at_height(10), 500);
+    assert_eq!(controller.coinbase_reward_at_height(19), 500);
+    assert_eq!(controller.coinbase_reward_at_height(20), 250);
+    assert_eq!(controller.coinbase_reward_at_height(1000), 250);
+}
+
+fn queue_key_registers(controller: &mut MocknetController, seed: u8, count: usize) {
+    let mut keychain = make_keychain(seed);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    for _ in 0..count {
+        let op = make_leader_key_register(&mut keychain, &tip);
+        controller.submit_operation(op, &mut op_signer);
+    }
+}
+
+#[test]
+fn mocknet_mine_until_queue_empty() {
+    let mut conf = Config::default();
+    conf.mocknet.max_ops_per_block = Some(2);
+    let mut controller = make_mocknet_controller(conf);
+    queue_key_registers(&mut controller, 14, 5);
+
+    let start_height = controller.get_chain_tip().block_snapshot.block_height;
+    let tip = controller.mine_until_queue_empty(3).unwrap();
+    assert_eq!(tip.block_snapshot.block_height, start_height + 3);
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+    assert!(controller.take_queue().is_empty());
+}
+
+#[test]
+fn mocknet_mine_until_queue_empty_hits_block_cap() {
+    let mut conf = Config::default();
+    conf.mocknet.max_ops_per_block = Some(1);
+    let mut controller = make_mocknet_controller(conf);
+    queue_key_registers(&mut controller, 15, 5);
+
+    match controller.mine_until_queue_empty(3) {
+        Err(BurnchainControllerError::QueueNotDrained(remaining)) => assert_eq!(remaining, 2),
+        _ => panic!("expected the queue not to drain")
+    }
+}
+
+#[test]
+fn mocknet_mine_until_queue_empty_skips_refused_blocks() {
+    let mut conf = Config::default();
+    conf.mocknet.max_ops_per_block = Some(1);
+    let mut controller = make_mocknet_controller(conf);
+    queue_key_registers(&mut controller, 16, 2);
+
+    // refuse the first block only
+    let attempts = Arc::new(Mutex::new(0));
+    let validator_attempts = attempts.clone();
+    controller.set_block_validator(Box::new(move |_: &[BlockstackOperationType]| {
+        let mut attempts = validator_attempts.lock().unwrap();
+        *attempts += 1;
+        if *attempts == 1 {
+            Err("first block".to_string())
+        } else {
+            Ok(())
+        }
+    }));
+
+    let start_height = controller.get_chain_tip().block_snapshot.block_height;
+    let tip = controller.mine_until_queue_empty(2).unwrap();
+    assert_eq!(tip.block_snapshot.block_height, start_height + 2);
+    assert_eq!(*attempts.lock().unwrap(), 3);
+}
+
+#[test]
+fn mocknet_distance_from() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+    let ancestor = controller.sync().block_snapshot;
+    for _ in 0..3 {
+        controller.sync();
+    }
+    let orphaned = controller.get_chain_tip().block_snapshot;
+
+    assert_eq!(controller.distance_from(&ancestor).unwrap(), Some(3));
+    assert_eq!(controller.distance_from(&orphaned).unwrap(), Some(0));
+
+    controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    assert_eq!(controller.distance_from(&ancestor).unwrap(), Some(4));
+    assert_eq!(controller.distance_from(&orphaned).unwrap(), None);
+}
+
+fn chaos_included_keys(seed: u64) -> (Vec<VRFPublicKey>, usize) {
+    let mut conf = Config::default();
+    conf.mocknet.chaos.seed = seed;
+    conf.mocknet.chaos.inclusion_probability = 0.5;
+    let mut controller = make_mocknet_controller(conf);
+    queue_key_registers(&mut controller, 16, 20);
+
+    let tip = controller.sync();
+    let included = tip.state_transition.accepted_ops.iter().map(leader_key_public_key).collect();
+    (included, controller.take_queue().len())
+}
+
+#[test]
+fn mocknet_chaos_inclusion_probability() {
+    let (included, requeued) = chaos_included_keys(7);
+    assert!(included.len() > 0 && included.len() < 20);
+    assert_eq!(included.len() + requeued, 20);
+
+    let (included_again, requeued_again) = chaos_included_keys(7);
+    assert_eq!(included, included_again);
+    assert_eq!(requeued, requeued_again);
+}
+
+fn winning_block_header_hash(tip: &BurnchainTip) -> Option<BlockHeaderHash> {
+    tip.state_transition.accepted_ops.iter()
+        .filter_map(|op| match op {
+            BlockstackOperationType::LeaderBlockCommit(commit) if commit.txid == tip.block_snapshot.winning_block_txid => Some(commit.block_header_hash),
+            _ => None
+        })
+        .next()
+}
+
+#[test]
+fn mocknet_sortition_winners_in_range() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut miner = MockMiner::new(make_keychain(17), 1000);
+    miner.auto_renew_keys = true;
+
+    let mut expected = vec![];
+    // key registration only, then two winning rounds, an empty block and another winning round
+    for round in 0..5 {
+        let tip = if round == 3 {
+            controller.sync()
+        } else {
+            miner.simulate_round(&mut controller)
+        };
+        expected.push((tip.block_snapshot.block_height, winning_block_header_hash(&tip)));
+    }
+    assert_eq!(expected.iter().filter(|(_, winner)| winner.is_some()).count(), 3);
+
+    let winners = controller.sortition_winners_in_range(1, 5).unwrap();
+    assert_eq!(winners, expected);
+}
+
+/// Mines 4 blocks, then a key register carrying the genesis consensus hash.
+fn mine_stale_key_register(controller: &mut MocknetController) -> BurnchainTip {
+    let genesis = controller.get_chain_tip();
+    for _ in 0..4 {
+        controller.sync();
+    }
+    let mut keychain = make_keychain(18);
+    let mut op_signer = keychain.generate_op_signer();
+    let op = make_leader_key_register(&mut keychain, &genesis);
+    controller.submit_operation(op, &mut op_signer);
+    controller.sync()
+}
+
+#[test]
+fn mocknet_with_custom_burnchain() {
+    let conf = Config::default();
+    let mut burnchain = Burnchain::new(&conf.get_burn_db_path(), &conf.burnchain.chain, &"regtest".to_string()).unwrap();
+    burnchain.first_block_height = 100;
+    burnchain.consensus_hash_lifetime = 2;
+
+    let mut controller = MocknetController::with_burnchain(conf, burnchain);
+    let genesis = controller.start();
+    assert_eq!(genesis.block_snapshot.block_height, 100);
+    assert_eq!(controller.reward_cycle_of_height(119), 0);
+    assert_eq!(controller.reward_cycle_of_height(120), 1);
+
+    let tip = mine_stale_key_register(&mut controller);
+    assert_eq!(tip.block_snapshot.block_height, 105);
+    assert!(tip.state_transition.accepted_ops.is_empty());
+
+    // the regtest lifetime still accepts the same registration
+    let mut default_controller = make_mocknet_controller(Config::default());
+    let tip = mine_stale_key_register(&mut default_controller);
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+}
+
+#[test]
+fn mocknet_secs_to_next_reward_cycle() {
+    let mut conf = Config::default();
+    conf.mocknet.reward_cycle_length = 10;
+    let controller = make_mocknet_controller(conf.clone());
+    assert_eq!(controller.secs_to_next_reward_cycle(), None);
+
+    conf.mocknet.block_time_secs = Some(30);
+    conf.node.working_dir = Config::default().node.working_dir;
+    let mut controller = make_mocknet_controller(conf);
+    for _ in 0..4 {
+        controller.sync();
+    }
+    assert_eq!(controller.secs_to_next_reward_cycle(), Some(6 * 30));
+
+    for _ in 0..6 {
+        controller.sync();
+    }
+    // height 10 starts the second cycle
+    assert_eq!(controller.secs_to_next_reward_cycle(), Some(10 * 30));
+}
+
+#[test]
+fn mocknet_rejects_zero_seed_commits() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(19);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+
+    let mut zero_seed = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    if let BlockstackOperationType::LeaderBlockCommit(ref mut op) = zero_seed {
+        op.new_seed = VRFSeed([0u8; 32]);
+    }
+    assert_eq!(controller.try_submit_operation(zero_seed, &mut op_signer), Err(RejectReason::ZeroSeed));
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+}
+
+#[test]
+fn mocknet_subscribe_receives_controller_events() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let events = controller.subscribe();
+
+    let mut keychain = make_keychain(20);
+    let mut op_signer = keychain.generate_op_signer();
+    let genesis = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &genesis);
+    // the same key registered twice: only the first one is accepted.  Identical submissions
+    // are refused while the first is queued, so the second one differs in its memo.
+    let mut duplicate = op.clone();
+    if let BlockstackOperationType::LeaderKeyRegister(ref mut op) = duplicate {
+        op.memo = vec![1];
+    }
+    assert!(controller.submit_operation(op, &mut op_signer));
+    assert!(controller.submit_operation(duplicate, &mut op_signer));
+    let tip = controller.sync();
+    let txids: Vec<_> = (1..3).map(|vtxindex| Txid(Sha256Sum::from_data(format!("1::{}", vtxindex).as_bytes()).0)).collect();
+
+    controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    let new_tip = controller.get_chain_tip();
+
+    let received: Vec<_> = events.try_iter().collect();
+    assert_eq!(received[0], ControllerEvent::BlockMined { block_height: 1, burn_header_hash: tip.block_snapshot.burn_header_hash });
+    assert_eq!(received[1], ControllerEvent::OpAccepted { block_height: 1, txid: txids[0] });
+    assert_eq!(received[2], ControllerEvent::OpRejected { block_height: 1, txid: txids[1] });
+    match received[3] {
+        ControllerEvent::BlockMined { block_height: 1, .. } => {},
+        ref e => panic!("unexpected event {:?}", e)
+    }
+    match received[4] {
+        ControllerEvent::BlockMined { block_height: 2, .. } => {},
+        ref e => panic!("unexpected event {:?}", e)
+    }
+    assert_eq!(received[5], ControllerEvent::Reorg {
+        fork_point_height: 0,
+        new_tip: new_tip.block_snapshot.burn_header_hash,
+        orphaned_ops: vec![txids[0]],
+    });
+    assert_eq!(received.len(), 6);
+}
+
+#[test]
+fn mocknet_rejects_commits_with_expired_keys() {
+    let mut conf = Config::default();
+    conf.mocknet.key_expiry_blocks = Some(3);
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(21);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+    assert_eq!(key.block_height, 1);
+
+    controller.sync();
+    controller.sync();
+    // would be mined at height 4, 3 blocks after the key
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+    controller.take_queue();
+
+    controller.sync();
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([2u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Err(RejectReason::ExpiredLeaderKey));
+}
+
+#[test]
+fn mocknet_drops_queued_commits_whose_key_expires() {
+    let mut conf = Config::default();
+    conf.mocknet.key_expiry_blocks = Some(3);
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(190);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+    controller.sync();
+    controller.sync();
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+
+    // a block mined around the queue: the commit would now land 4 blocks after its key
+    let tip = controller.get_chain_tip();
+    let filler = make_leader_key_register(&mut keychain, &tip);
+    controller.bulk_import_ops(vec![(tip.block_snapshot.block_height + 1, filler)]).unwrap();
+
+    let tip = controller.sync();
+    assert_eq!(tip.block_snapshot.block_height, 5);
+    assert!(tip.state_transition.accepted_ops.is_empty());
+    assert!(controller.queued_by_type().block_commits.is_empty());
+}
+
+#[test]
+fn mocknet_burn_sample_points_partition_the_range() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut miners = vec![make_keychain(22), make_keychain(23)];
+
+    let tip = controller.get_chain_tip();
+    let mut public_keys = vec![];
+    for miner in miners.iter_mut() {
+        let op = make_leader_key_register(miner, &tip);
+        public_keys.push(leader_key_public_key(&op));
+        controller.submit_operation(op, &mut miner.generate_op_signer());
+    }
+    let key_tip = controller.sync();
+    assert!(key_tip.burn_sample_points().is_empty());
+
+    for (i, miner) in miners.iter().enumerate() {
+        let key = find_leader_key(&key_tip, &public_keys[i]);
+        let commit = make_block_commit(miner, &key, BlockHeaderHash([i as u8 + 1; 32]), 1000 * (i as u64 + 1));
+        controller.submit_operation(commit, &mut miner.generate_op_signer());
+    }
+    let tip = controller.sync();
+
+    let points = tip.burn_sample_points();
+    assert_eq!(points.len(), 2);
+    assert_eq!(points.iter().map(|point| point.burns).sum::<u128>(), 3000);
+    assert_eq!(points[0].range_start, Uint256::zero());
+    assert_eq!(points[0].range_end, points[1].range_start);
+    assert_eq!(points[1].range_end, Uint256::max());
+    assert!(points[0].range_start < points[0].range_end);
+    assert!(points[1].range_start < points[1].range_end);
+}
+
+/// Register one key per miner, then queue a commit per miner burning `burns[i]`.
+/// Returns the commits' block header hashes.
+fn queue_competing_commits(controller: &mut MocknetController, miners: &mut [Keychain], burns: &[u64], round: u8) -> Vec<BlockHeaderHash> {
+    let tip = controller.get_chain_tip();
+    let mut public_keys = vec![];
+    for miner in miners.iter_mut() {
+        let op = make_leader_key_register(miner, &tip);
+        public_keys.push(leader_key_public_key(&op));
+        controller.submit_operation(op, &mut miner.generate_op_signer());
+    }
+    let key_tip = controller.sync();
+
+    let mut block_hashes = vec![];
+    for (i, miner) in miners.iter().enumerate() {
+        let key = find_leader_key(&key_tip, &public_keys[i]);
+        let block_hash = BlockHeaderHash([round * 16 + i as u8 + 1; 32]);
+        controller.submit_operation(make_block_commit(miner, &key, block_hash, burns[i]), &mut miner.generate_op_signer());
+        block_hashes.push(block_hash);
+    }
+    block_hashes
+}
+
+#[test]
+fn mocknet_set_next_sortition_vrf_selects_the_winner() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut miners = vec![make_keychain(24), make_keychain(25)];
+
+    // 1:3 burns: the first commit owns the bottom quarter of the range
+    for (round, (value_msb, expected_winner)) in [(0x00u8, 0usize), (0x80, 1), (0x10, 0), (0xf0, 1)].iter().enumerate() {
+        let block_hashes = queue_competing_commits(&mut controller, &mut miners, &[1000, 3000], round as u8);
+        let mut value = [0u8; 32];
+        value[31] = *value_msb;
+        controller.set_next_sortition_vrf(value);
+        let tip = controller.sync();
+        assert_eq!(winning_block_header_hash(&tip), Some(block_hashes[*expected_winner]));
+    }
+}
+
+#[test]
+fn mocknet_vacuum_reclaims_pruned_space() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(26);
+    let mut op_signer = keychain.generate_op_signer();
+    for _ in 0..5 {
+        let tip = controller.get_chain_tip();
+        for _ in 0..50 {
+            let mut op = make_leader_key_register(&mut keychain, &tip);
+            if let BlockstackOperationType::LeaderKeyRegister(ref mut op) = op {
+                op.memo = vec![0xab; 1024];
+            }
+            controller.submit_operation(op, &mut op_signer);
+        }
+        controller.sync();
+    }
+
+    // prune the key registrations of the blocks orphaned by a reorg
+    controller.reorg(5, vec![vec![]; 6]).unwrap();
+    controller.take_queue();
+    let orphaned_sortition_ids: Vec<_> = controller.sortdb_ref().get_all_snapshots().unwrap().into_iter()
+        .filter(|snapshot| controller.distance_from(snapshot).unwrap().is_none())
+        .map(|snapshot| snapshot.sortition_id)
+        .collect();
+    assert_eq!(orphaned_sortition_ids.len(), 5);
+    for sortition_id in orphaned_sortition_ids.iter() {
+        let sql = format!("DELETE FROM leader_keys WHERE sortition_id = '{}'", sortition_id.to_hex());
+        controller.sortdb_ref().conn().execute_batch(&sql).unwrap();
+    }
+
+    let reclaimed = controller.vacuum().unwrap();
+    assert!(reclaimed > 0);
+    assert_eq!(controller.vacuum().unwrap(), 0);
+}
+
+#[test]
+fn mocknet_multisig_signer_threshold() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(27);
+    let tip = controller.get_chain_tip();
+
+    let secret_keys: Vec<_> = (0..3).map(|_| Secp256k1PrivateKey::new()).collect();
+    let public_keys: Vec<_> = secret_keys.iter().map(Secp256k1PublicKey::from_private).collect();
+    let hash_mode = AddressHashMode::SerializeP2SH;
+    let multisig_address = StacksAddress::from_public_keys(hash_mode.to_version_testnet(), &hash_mode, 2, &public_keys).unwrap();
+    let make_multisig_key_register = |keychain: &mut Keychain| {
+        let mut op = make_leader_key_register(keychain, &tip);
+        if let BlockstackOperationType::LeaderKeyRegister(ref mut op) = op {
+            op.address = multisig_address;
+        }
+        op
+    };
+
+    let mut two_of_three = BurnchainOpSigner::new_multisig(public_keys.clone(), secret_keys[..2].to_vec(), 2, false);
+    let op = make_multisig_key_register(&mut keychain);
+    assert_eq!(controller.try_submit_operation(op, &mut two_of_three), Ok(()));
+
+    let mut one_of_three = BurnchainOpSigner::new_multisig(public_keys.clone(), secret_keys[2..].to_vec(), 2, false);
+    let op = make_multisig_key_register(&mut keychain);
+    assert_eq!(controller.try_submit_operation(op, &mut one_of_three), Err(RejectReason::InsufficientSignatures));
+
+    // keys outside the signer's set don't count towards the threshold
+    let outsider = Secp256k1PrivateKey::new();
+    let mut forged = BurnchainOpSigner::new_multisig(public_keys.clone(), vec![secret_keys[0].clone(), outsider.clone()], 2, false);
+    let op = make_multisig_key_register(&mut keychain);
+    assert!(!controller.submit_operation(op, &mut forged));
+
+    // neither can a signer over other keys, even one that meets its own threshold
+    let mut outsider_signer = BurnchainOpSigner::new(outsider, false);
+    let op = make_multisig_key_register(&mut keychain);
+    assert_eq!(controller.try_submit_operation(op, &mut outsider_signer), Err(RejectReason::SignerMismatch));
+
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+}
+
+#[test]
+fn mocknet_multisig_keychain_signs_its_own_ops() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let secret_keys: Vec<_> = (0..3).map(|_| Secp256k1PrivateKey::new()).collect();
+    let mut keychain = Keychain::new(secret_keys, 2, AddressHashMode::SerializeP2SH);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    assert_eq!(controller.try_submit_operation(op, &mut op_signer), Ok(()));
+    let key_tip = controller.sync();
+    let key = find_leader_key(&key_tip, &public_key);
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+}
+
+#[test]
+fn mocknet_to_dot_marks_canonical_fork() {
+    let mut controller = make_mocknet_controller(Config::default());
+    for _ in 0..3 {
+        controller.sync();
+    }
+    let orphaned = controller.get_chain_tip().block_snapshot;
+    let new_tip = controller.reorg(1, vec![vec![], vec![]]).unwrap().block_snapshot;
+
+    let dot = controller.to_dot().unwrap();
+    assert!(dot.starts_with("digraph mocknet {"));
+
+    let orphaned_hash = orphaned.burn_header_hash.to_hex();
+    let replacement = controller.parent_snapshot(&new_tip).unwrap().unwrap();
+    let replacement_hash = replacement.burn_header_hash.to_hex();
+    let fork_point_hash = orphaned.parent_burn_header_hash.to_hex();
+    assert_eq!(replacement.parent_burn_header_hash, orphaned.parent_burn_header_hash);
+
+    assert!(dot.contains(&format!("\"{}\" [label=\"{}\\n{}\", style=dashed];", orphaned_hash, orphaned.block_height, &orphaned_hash[..8])));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\" [style=dashed];", orphaned_hash, fork_point_hash)));
+    assert!(dot.contains(&format!("\"{}\" [label=\"{}\\n{}\", style=bold];", replacement_hash, replacement.block_height, &replacement_hash[..8])));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\" [style=bold];", replacement_hash, fork_point_hash)));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\" [style=bold];", new_tip.burn_header_hash.to_hex(), replacement_hash)));
+}
+
+#[test]
+fn mocknet_require_signature_for_commits_only() {
+    let mut conf = Config::default();
+    conf.mocknet.require_signature_for.insert(OpTypeKind::LeaderBlockCommit);
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(28);
+    let mut op_signer = keychain.generate_op_signer();
+    let mut unsigned = keychain.generate_op_signer();
+    unsigned.dispose();
+
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    assert_eq!(controller.try_submit_operation(op, &mut unsigned), Ok(()));
+    let key = find_leader_key(&controller.sync(), &public_key);
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit.clone(), &mut unsigned), Err(RejectReason::MissingSignature));
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+
+    // an unsigned submission still needs the keys the operation names
+    let mut other_unsigned = make_keychain(191).generate_op_signer();
+    other_unsigned.dispose();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    assert_eq!(controller.try_submit_operation(op, &mut other_unsigned), Err(RejectReason::SignerMismatch));
+}
+
+#[test]
+fn mocknet_predict_header_hash_matches_mined_block() {
+    let mut conf = Config::default();
+    conf.mocknet.chain_seed = vec![0xc0, 0xff, 0xee];
+    let mut controller = make_mocknet_controller(conf.clone());
+
+    let seed = MocknetController::header_seed(&conf.mocknet.chain_seed, &[]);
+    for _ in 0..3 {
+        let parent_hash = controller.get_chain_tip().block_snapshot.burn_header_hash;
+        let predicted = MocknetController::predict_header_hash(&parent_hash, &seed);
+        assert_eq!(controller.sync().block_snapshot.burn_header_hash, predicted);
+    }
+}
+
+#[test]
+fn mocknet_fresh_chain_tip_sees_other_miners_blocks() {
+    let conf = Config::default();
+    let mut reader = make_mocknet_controller(conf.clone());
+    let mut miner = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(29);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = miner.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    miner.submit_operation(op, &mut op_signer);
+    let mined = miner.sync();
+
+    let cached = reader.get_chain_tip_with(ConsistencyLevel::Cached).unwrap();
+    assert_eq!(cached.block_snapshot.burn_header_hash, reader.get_chain_tip().block_snapshot.burn_header_hash);
+    assert_eq!(cached.block_snapshot.block_height, 0);
+
+    let fresh = reader.get_chain_tip_with(ConsistencyLevel::Fresh).unwrap();
+    assert_eq!(fresh.block_snapshot.burn_header_hash, mined.block_snapshot.burn_header_hash);
+    let txids = |tip: &BurnchainTip| -> Vec<Txid> { tip.state_transition.accepted_ops.iter().map(|op| op.txid()).collect() };
+    assert_eq!(txids(&fresh), txids(&mined));
+    assert_eq!(txids(&fresh).len(), 1);
+}
+
+#[test]
+fn mocknet_export_ops_csv() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(30);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let tip = controller.sync();
+    let key = find_leader_key(&tip, &public_key);
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([3u8; 32]), 12345);
+    controller.submit_operation(commit, &mut op_signer);
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller.submit_operation(op, &mut op_signer);
+    let tip = controller.sync();
+    controller.sync();
+
+    let csv = controller.export_ops_csv().unwrap();
+    let rows: Vec<_> = csv.lines().collect();
+    assert_eq!(rows[0], "block_height,txid,op_type,burn_fee,address");
+    assert_eq!(rows.len(), 4);
+
+    let address = keychain.get_address();
+    assert_eq!(rows[1], format!("1,{},leader_key_register,,{}", key.txid.to_hex(), address));
+    let commit_txid = tip.state_transition.accepted_ops.iter()
+        .find(|op| OpTypeKind::of(op) == OpTypeKind::LeaderBlockCommit)
+        .unwrap()
+        .txid();
+    assert_eq!(rows[2], format!("2,{},leader_block_commit,12345,{}", commit_txid.to_hex(), address));
+    assert!(rows[3].starts_with("2,"));
+    assert!(rows[3].contains(",leader_key_register,,"));
+}
+
+#[test]
+fn mocknet_disabled_tip_cache_reads_db_tip() {
+    let delete_tip = |controller: &MocknetController, tip: &BurnchainTip| {
+        let sql = format!("DELETE FROM snapshots WHERE burn_header_hash = '{}'", tip.block_snapshot.burn_header_hash.to_hex());
+        controller.sortdb_ref().conn().execute_batch(&sql).unwrap();
+    };
+
+    let mut cached = make_mocknet_controller(Config::default());
+    let mut conf = Config::default();
+    conf.mocknet.disable_tip_cache = true;
+    let mut uncached = make_mocknet_controller(conf);
+
+    for controller in [&mut cached, &mut uncached] {
+        controller.sync();
+        let parent = controller.sync();
+        let tip = controller.sync();
+        delete_tip(controller, &tip);
+
+        // the DB now says the chain ends at the parent
+        let fresh = controller.get_chain_tip_with(ConsistencyLevel::Fresh).unwrap();
+        assert_eq!(fresh.block_snapshot.burn_header_hash, parent.block_snapshot.burn_header_hash);
+    }
+
+    assert_eq!(cached.get_chain_tip().block_snapshot.block_height, 3);
+    assert_eq!(uncached.get_chain_tip().block_snapshot.block_height, 2);
+    assert_eq!(uncached.get_chain_tip_with(ConsistencyLevel::Cached).unwrap().block_snapshot.block_height, 2);
+}
+
+#[test]
+fn mocknet_released_private_blocks_reorg_the_public_chain() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+    controller.sync();
+
+    let private_blocks: Vec<_> = (0..3).map(|_| controller.mine_private(vec![])).collect();
+    assert_eq!(private_blocks[0].header.block_height, 3);
+    assert_eq!(private_blocks[2].header.block_height, 5);
+    // withheld blocks are not applied
+    assert_eq!(controller.get_chain_tip().block_snapshot.block_height, 2);
+
+    let mut keychain = make_keychain(31);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller.submit_operation(op, &mut op_signer);
+    let orphaned = controller.sync();
+    controller.sync();
+    assert_eq!(orphaned.state_transition.accepted_ops.len(), 1);
+
+    let private_tip_hash = private_blocks[2].header.block_hash.clone();
+    let new_tip = controller.release_private(private_blocks).unwrap();
+    assert_eq!(new_tip.block_snapshot.block_height, 5);
+    assert_eq!(new_tip.block_snapshot.burn_header_hash, private_tip_hash);
+    assert_eq!(controller.get_chain_tip().block_snapshot.burn_header_hash, private_tip_hash);
+    assert_eq!(controller.distance_from(&orphaned.block_snapshot).unwrap(), None);
+
+    // the public branch's key registration is queued again
+    assert_eq!(controller.last_orphaned_ops().len(), 1);
+    assert_eq!(controller.take_queue().len(), 1);
+}
+
+#[test]
+fn mocknet_batch_window_holds_back_fresh_ops() {
+    let mut conf = Config::default();
+    conf.mocknet.batch_window_secs = 10;
+    let mut controller = make_mocknet_controller(conf);
+    let clock = MockClock::new(1000);
+    controller.set_clock_source(Box::new(clock.clone()));
+
+    let mut keychain = make_keychain(32);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller.submit_operation(op, &mut op_signer);
+
+    let tip = controller.sync();
+    assert!(tip.state_transition.accepted_ops.is_empty());
+    assert_eq!(tip.block_snapshot.burn_header_timestamp, 1000);
+
+    clock.advance(9);
+    assert!(controller.sync().state_transition.accepted_ops.is_empty());
+
+    clock.advance(1);
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+    assert_eq!(tip.block_snapshot.burn_header_timestamp, 1010);
+}
+
+#[test]
+fn mocknet_config_reflects_construction_settings() {
+    let mut conf = Config::default();
+    conf.mocknet.block_time_secs = Some(30);
+    conf.mocknet.max_ops_per_block = Some(7);
+    conf.mocknet.key_expiry_blocks = Some(12);
+    let controller = make_mocknet_controller(conf.clone());
+
+    let mocknet = &controller.config().mocknet;
+    assert_eq!(mocknet.block_time_secs, Some(30));
+    assert_eq!(mocknet.max_ops_per_block, Some(7));
+    assert_eq!(mocknet.key_expiry_blocks, Some(12));
+    assert_eq!(mocknet.reward_cycle_length, conf.mocknet.reward_cycle_length);
+    assert_eq!(controller.config().get_burn_db_file_path(), conf.get_burn_db_file_path());
+}
+
+#[test]
+fn mocknet_genesis_leader_keys_are_available_after_start() {
+    let mut conf = Config::default();
+    conf.mocknet.genesis_leader_keys = 5;
+    let mut controller = make_mocknet_controller(conf);
+
+    let keys = controller.available_leader_keys().unwrap();
+    assert_eq!(keys.len(), 5);
+    for (index, key) in keys.iter().enumerate() {
+        let mut keychain = controller.genesis_keychain(index as u64);
+        assert_eq!(key.public_key, keychain.rotate_vrf_keypair(0));
+        assert_eq!(key.address, keychain.get_address());
+    }
+
+    // any of them can be committed with right away
+    let keychain = controller.genesis_keychain(3);
+    let mut op_signer = keychain.generate_op_signer();
+    let commit = make_block_commit(&keychain, &keys[3], BlockHeaderHash([4u8; 32]), 1000);
+    controller.submit_operation(commit, &mut op_signer);
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.consumed_leader_keys.len(), 1);
+
+    let remaining = controller.available_leader_keys().unwrap();
+    assert_eq!(remaining.len(), 4);
+    assert!(remaining.iter().all(|key| key.public_key != keys[3].public_key));
+}
+
+#[test]
+fn mocknet_detects_equivocating_commits() {
+    for reject_equivocations in [false, true] {
+        let mut conf = Config::default();
+        conf.mocknet.reject_equivocations = reject_equivocations;
+        let mut controller = make_mocknet_controller(conf);
+
+        let mut keychain = make_keychain(33);
+        let mut op_signer = keychain.generate_op_signer();
+        let tip = controller.get_chain_tip();
+        let op = make_leader_key_register(&mut keychain, &tip);
+        let public_key = leader_key_public_key(&op);
+        controller.submit_operation(op, &mut op_signer);
+        let key = find_leader_key(&controller.sync(), &public_key);
+        assert!(controller.last_equivocations().is_empty());
+
+        for block_hash in [BlockHeaderHash([5u8; 32]), BlockHeaderHash([6u8; 32])] {
+            let commit = make_block_commit(&keychain, &key, block_hash, 1000);
+            controller.submit_operation(commit, &mut op_signer);
+        }
+        let tip = controller.sync();
+
+        let equivocations = controller.last_equivocations().to_vec();
+        assert_eq!(equivocations.len(), 1);
+        assert_eq!(equivocations[0].0, public_key);
+        assert_eq!(equivocations[0].1.len(), 2);
+
+        let commit_txids: Vec<_> = tip.state_transition.accepted_ops.iter()
+            .filter(|op| OpTypeKind::of(op) == OpTypeKind::LeaderBlockCommit)
+            .map(|op| op.txid())
+            .collect();
+        if reject_equivocations {
+            assert_eq!(commit_txids, vec![equivocations[0].1[0].clone()]);
+        }
+
+        controller.sync();
+        assert!(controller.last_equivocations().is_empty());
+    }
+}
+
+#[test]
+fn mocknet_ordering_seed_reorders_ties_only() {
+    let mine_with_ordering_seed = |ordering_seed: u64| -> (BurnchainHeaderHash, Vec<VRFPublicKey>) {
+        let mut conf = Config::default();
+        conf.mocknet.op_ordering = OpOrdering::BurnFee;
+        conf.mocknet.ordering_seed = ordering_seed;
+        let mut controller = make_mocknet_controller(conf);
+
+        let mut keychain = make_keychain(34);
+        let mut op_signer = keychain.generate_op_signer();
+        let tip = controller.get_chain_tip();
+        for _ in 0..8 {
+            let op = make_leader_key_register(&mut keychain, &tip);
+            controller.submit_operation(op, &mut op_signer);
+        }
+        let tip = controller.sync();
+
+        let mut keys: Vec<_> = tip.state_transition.accepted_ops.iter()
+            .filter_map(|op| match op {
+                BlockstackOperationType::LeaderKeyRegister(op) => Some((op.vtxindex, op.public_key.clone())),
+                _ => None
+            })
+            .collect();
+        keys.sort_by_key(|(vtxindex, _)| *vtxindex);
+        (tip.block_snapshot.burn_header_hash, keys.into_iter().map(|(_, public_key)| public_key).collect())
+    };
+
+    let (hash_a, keys_a) = mine_with_ordering_seed(1);
+    let (hash_b, keys_b) = mine_with_ordering_seed(2);
+    let (hash_c, keys_c) = mine_with_ordering_seed(1);
+
+    assert_eq!(keys_a.len(), 8);
+    assert_ne!(keys_a, keys_b);
+    assert_eq!(keys_a, keys_c);
+    assert_eq!(hash_a, hash_b);
+    assert_eq!(hash_a, hash_c);
+}
+
+#[test]
+fn mocknet_sortition_hash_matches_snapshots() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut miner = MockMiner::new(make_keychain(35), 1000);
+    miner.auto_renew_keys = true;
+    for _ in 0..4 {
+        miner.simulate_round(&mut controller);
+    }
+    controller.sync();
+
+    let tip_height = controller.get_chain_tip().block_snapshot.block_height;
+    let mut sortitions = 0;
+    for height in 0..(tip_height + 1) {
+        let snapshot = controller.sortdb_ref().get_all_snapshots().unwrap().into_iter()
+            .find(|snapshot| snapshot.block_height == height)
+            .unwrap();
+        assert_eq!(controller.sortition_hash(height).unwrap(), Some(snapshot.sortition_hash));
+        if snapshot.sortition {
+            sortitions += 1;
+        }
+    }
+    assert!(sortitions >= 3);
+    assert!(tip_height > 0);
+    assert_eq!(controller.sortition_hash(tip_height + 1).unwrap(), None);
+}
+
+#[test]
+fn mocknet_exported_sortition_db_opens_read_only() {
+    let conf = Config::default();
+    let mut controller = make_mocknet_controller(conf.clone());
+    let mut miner = MockMiner::new(make_keychain(36), 1000);
+    miner.auto_renew_keys = true;
+    for _ in 0..3 {
+        miner.simulate_round(&mut controller);
+    }
+    let tip = controller.get_chain_tip().block_snapshot;
+
+    let export_path = PathBuf::from(&conf.node.working_dir).join("exported-sortdb");
+    controller.export_sortition_db(&export_path).unwrap();
+
+    let db = SortitionDB::open(export_path.to_str().unwrap(), false).unwrap();
+    let exported_tip = SortitionDB::get_canonical_burn_chain_tip_stubbed(db.conn()).unwrap();
+    assert_eq!(exported_tip, tip);
+
+    let ic = db.index_conn();
+    let ancestor = SortitionDB::get_ancestor_snapshot(&ic, 1, &exported_tip.sortition_id).unwrap().unwrap();
+    assert_eq!(Some(ancestor), controller.parent_snapshot(&controller.parent_snapshot(&tip).unwrap().unwrap()).unwrap());
+}
+
+#[test]
+fn mocknet_min_commits_for_sortition() {
+    let mut conf = Config::default();
+    conf.mocknet.min_commits_for_sortition = 2;
+    let mut controller = make_mocknet_controller(conf);
+    let mut miners = vec![make_keychain(37), make_keychain(38)];
+
+    let block_hashes = queue_competing_commits(&mut controller, &mut miners[..1], &[1000], 0);
+    assert_eq!(block_hashes.len(), 1);
+    for _ in 0..2 {
+        // the lone commit is left out of the block, but stays queued
+        let tip = controller.sync();
+        assert!(!tip.block_snapshot.sortition);
+        assert!(tip.state_transition.accepted_ops.is_empty());
+        assert_eq!(controller.queued_by_type().block_commits.len(), 1);
+    }
+
+    // key registrations still get mined while the commit waits for company
+    queue_competing_commits(&mut controller, &mut miners[1..], &[2000], 1);
+    assert_eq!(controller.queued_by_type().block_commits.len(), 2);
+    let tip = controller.sync();
+    assert!(tip.block_snapshot.sortition);
+    assert_eq!(tip.state_transition.accepted_ops.len(), 2);
+    assert!(controller.take_queue().is_empty());
+}
+
+#[test]
+fn mocknet_explain_validation_lists_failed_checks() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(39);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    assert!(controller.explain_validation(&op).is_valid());
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+    controller.sync();
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([7u8; 32]), 1000);
+    let report = controller.explain_validation(&commit);
+    assert!(report.is_valid());
+    assert!(report.checks.iter().any(|check| check.rule == ValidationRule::KeyAddressMatch));
+
+    let mut missing_key = key.clone();
+    missing_key.vtxindex = 7;
+    let commit = make_block_commit(&keychain, &missing_key, BlockHeaderHash([8u8; 32]), 0);
+    let report = controller.explain_validation(&commit);
+    assert_eq!(report.failures(), vec![ValidationRule::BurnFee, ValidationRule::KeyReference]);
+    assert!(report.checks.iter().any(|check| check.rule == ValidationRule::ParentPointer && check.passed));
+}
+
+#[test]
+fn mocknet_explain_submission_reports_rejections() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(40);
+    let mut op_signer = keychain.generate_op_signer();
+
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    assert!(controller.explain_submission(&op, &op_signer).is_valid());
+    assert_eq!(controller.try_submit_operation(op.clone(), &mut op_signer), Ok(()));
+
+    assert_eq!(controller.try_submit_operation(op.clone(), &mut op_signer), Err(RejectReason::Duplicate));
+    assert_eq!(controller.explain_validation(&op).failures(), vec![ValidationRule::Duplicate]);
+
+    let mut outsider = make_keychain(41).generate_op_signer();
+    let other = make_leader_key_register(&mut keychain, &tip);
+    assert_eq!(controller.explain_submission(&other, &outsider).failures(), vec![ValidationRule::SignerMismatch]);
+    assert_eq!(controller.try_submit_operation(other, &mut outsider), Err(RejectReason::SignerMismatch));
+
+    let secret_keys: Vec<_> = (0..3).map(|_| Secp256k1PrivateKey::new()).collect();
+    let public_keys: Vec<_> = secret_keys.iter().map(Secp256k1PublicKey::from_private).collect();
+    let mut multisig_keychain = Keychain::new(secret_keys.clone(), 2, AddressHashMode::SerializeP2SH);
+    let mut one_of_three = BurnchainOpSigner::new_multisig(public_keys, secret_keys[..1].to_vec(), 2, false);
+    let op = make_leader_key_register(&mut multisig_keychain, &tip);
+    assert_eq!(controller.explain_submission(&op, &one_of_three).failures(), vec![ValidationRule::InsufficientSignatures]);
+    assert_eq!(controller.try_submit_operation(op, &mut one_of_three), Err(RejectReason::InsufficientSignatures));
+}
+
+#[test]
+fn mocknet_clock_control_drives_time_gated_inclusion() {
+    let mut conf = Config::default();
+    conf.mocknet.batch_window_secs = 30;
+    let mut controller = make_mocknet_controller(conf);
+
+    // the wall clock can't be moved
+    assert!(controller.advance_clock(1).is_err());
+    assert!(controller.set_clock(1).is_err());
+
+    controller.set_clock_source(Box::new(MockClock::new(0)));
+    controller.set_clock(5000).unwrap();
+
+    let mut keychain = make_keychain(40);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    controller.submit_operation(op, &mut op_signer);
+
+    controller.advance_clock(29).unwrap();
+    let tip = controller.sync();
+    assert!(tip.state_transition.accepted_ops.is_empty());
+    assert_eq!(tip.block_snapshot.burn_header_timestamp, 5029);
+
+    controller.advance_clock(1).unwrap();
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+    assert_eq!(tip.block_snapshot.burn_header_timestamp, 5030);
+}
+
+#[test]
+fn mocknet_dedup_window_rejects_recently_mined_ops() {
+    let mut conf = Config::default();
+    conf.mocknet.dedup_window_blocks = 3;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(41);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    assert_eq!(controller.try_submit_operation(op.clone(), &mut op_signer), Ok(()));
+    // already queued
+    assert_eq!(controller.try_submit_operation(op.clone(), &mut op_signer), Err(RejectReason::Duplicate));
+
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+    controller.sync();
+    controller.sync();
+    // mined at height 1, the window covers heights 1 to 3
+    assert_eq!(controller.try_submit_operation(op.clone(), &mut op_signer), Err(RejectReason::Duplicate));
+
+    controller.sync();
+    assert_eq!(controller.try_submit_operation(op, &mut op_signer), Ok(()));
+}
+
+#[test]
+fn mocknet_common_ancestor_is_the_fork_point() {
+    let mut controller = make_mocknet_controller(Config::default());
+    for _ in 0..5 {
+        controller.sync();
+    }
+    let fork_point = controller.get_chain_tip().block_snapshot;
+    assert_eq!(fork_point.block_height, 5);
+    controller.sync();
+    controller.sync();
+    let old_branch = controller.get_chain_tip().block_snapshot;
+
+    // replace heights 6 and 7 with a longer branch, then extend it
+    let new_branch = controller.reorg(2, vec![vec![]; 3]).unwrap().block_snapshot;
+    controller.sync();
+    let new_branch_tip = controller.get_chain_tip().block_snapshot;
+
+    assert_eq!(controller.common_ancestor(&old_branch, &new_branch).unwrap(), Some(fork_point.clone()));
+    assert_eq!(controller.common_ancestor(&new_branch_tip, &old_branch).unwrap(), Some(fork_point));
+    assert_eq!(controller.common_ancestor(&new_branch, &new_branch_tip).unwrap(), Some(new_branch));
+}
+
+#[test]
+fn mocknet_block_annotations_survive_until_reorged() {
+    let mut controller = make_mocknet_controller(Config::default());
+    for _ in 0..3 {
+        controller.sync();
+    }
+    controller.annotate_block(3, "attack starts here".to_string());
+    controller.annotate_block(10, "not mined yet".to_string());
+
+    for _ in 0..3 {
+        controller.sync();
+    }
+    assert_eq!(controller.block_annotation(3), Some("attack starts here"));
+    assert_eq!(controller.block_annotation(10), None);
+
+    // replacing blocks 3..6 drops the note on block 3
+    controller.reorg(4, vec![vec![]; 5]).unwrap();
+    assert_eq!(controller.block_annotation(3), None);
+}
+
+#[test]
+fn mocknet_requeued_commits_get_fee_bump() {
+    let mut conf = Config::default();
+    conf.mocknet.requeue_fee_bump_pct = 10;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(31);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    controller.submit_operation(commit, &mut op_signer);
+    let tip = controller.sync();
+    assert_eq!(tip.state_transition.accepted_ops.len(), 1);
+
+    controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    let requeued = controller.take_queue();
+    assert_eq!(requeued.len(), 1);
+    match requeued[0] {
+        BlockstackOperationType::LeaderBlockCommit(ref op) => assert_eq!(op.burn_fee, 1100),
+        _ => panic!("expected the orphaned block commit to be re-queued")
+    }
+}
+
+#[test]
+fn mocknet_total_ops_mined_accumulates() {
+    let mut controller = make_mocknet_controller(Config::default());
+    assert_eq!(controller.total_ops_mined(), 0);
+
+    let mut expected = 0;
+    for (i, num_ops) in [2, 0, 3].iter().enumerate() {
+        let mut keychain = make_keychain(40 + i as u8);
+        let mut op_signer = keychain.generate_op_signer();
+        for _ in 0..*num_ops {
+            let tip = controller.get_chain_tip();
+            let op = make_leader_key_register(&mut keychain, &tip);
+            controller.submit_operation(op, &mut op_signer);
+        }
+        controller.sync();
+        expected += num_ops;
+        assert_eq!(controller.total_ops_mined(), expected);
+    }
+
+    // the orphaned block's operations still count, and are counted again once re-mined
+    controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    assert_eq!(controller.total_ops_mined(), expected);
+    controller.sync();
+    expected += 3;
+    assert_eq!(controller.total_ops_mined(), expected);
+
+    // so do those of blocks appended off the canonical fork
+    let mut keychain = make_keychain(43);
+    let tip = controller.get_chain_tip();
+    let private_block = controller.mine_private(vec![make_leader_key_register(&mut keychain, &tip)]);
+    controller.sync();
+    controller.sync();
+    let canonical_tip = controller.release_private(vec![private_block]).unwrap();
+    assert!(canonical_tip.state_transition.accepted_ops.is_empty());
+    assert_eq!(controller.total_ops_mined(), expected + 1);
+}
+
+/// Mine a winning commit at height 2, then 10 empty blocks, and submit a commit building on it.
+fn submit_commit_on_old_parent(conf: Config) -> Result<(), RejectReason> {
+    let mut controller = make_mocknet_controller(conf);
+    submit_commit_on_old_parent_to(&mut controller)
+}
+
+fn submit_commit_on_old_parent_to(controller: &mut MocknetController) -> Result<(), RejectReason> {
+    let mut keychain = make_keychain(50);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let first_key = make_leader_key_register(&mut keychain, &tip);
+    let second_key = make_leader_key_register(&mut keychain, &tip);
+    let (first_public_key, second_public_key) = (leader_key_public_key(&first_key), leader_key_public_key(&second_key));
+    controller.submit_operation(first_key, &mut op_signer);
+    controller.submit_operation(second_key, &mut op_signer);
+    let key_tip = controller.sync();
+    let first_key = find_leader_key(&key_tip, &first_public_key);
+    let second_key = find_leader_key(&key_tip, &second_public_key);
+
+    let commit = make_block_commit(&keychain, &first_key, BlockHeaderHash([1u8; 32]), 1000);
+    controller.submit_operation(commit, &mut op_signer);
+    let parent_tip = controller.sync();
+    let parent_vtxindex = parent_tip.get_winning_tx_index().expect("commit did not win");
+    for _ in 0..10 {
+        controller.sync();
+    }
+
+    let mut commit = make_block_commit(&keychain, &second_key, BlockHeaderHash([2u8; 32]), 1000);
+    if let BlockstackOperationType::LeaderBlockCommit(ref mut op) = commit {
+        op.parent_block_ptr = parent_tip.block_snapshot.block_height as u32;
+        op.parent_vtxindex = parent_vtxindex as u16;
+    }
+    controller.try_submit_operation(commit, &mut op_signer)
+}
+
+#[test]
+fn mocknet_max_parent_depth_refuses_old_parents() {
+    assert_eq!(submit_commit_on_old_parent(Config::default()), Ok(()));
+
+    let mut conf = Config::default();
+    conf.mocknet.max_parent_depth = Some(5);
+    assert_eq!(submit_commit_on_old_parent(conf), Err(RejectReason::ParentTooOld));
+
+    let mut conf = Config::default();
+    conf.mocknet.max_parent_depth = Some(10);
+    assert_eq!(submit_commit_on_old_parent(conf), Ok(()));
+}
+
+#[test]
+fn mocknet_max_parent_depth_drops_commits_aging_in_the_queue() {
+    let mut conf = Config::default();
+    conf.mocknet.max_parent_depth = Some(10);
+    let mut controller = make_mocknet_controller(conf);
+    assert_eq!(submit_commit_on_old_parent_to(&mut controller), Ok(()));
+
+    // a block mined around the queue puts the parent 11 blocks below the tip
+    let mut keychain = make_keychain(51);
+    let tip = controller.get_chain_tip();
+    let key = make_leader_key_register(&mut keychain, &tip);
+    controller.bulk_import_ops(vec![(tip.block_snapshot.block_height + 1, key)]).unwrap();
+
+    let tip = controller.sync();
+    assert!(tip.state_transition.accepted_ops.is_empty());
+    assert!(controller.queued_by_type().block_commits.is_empty());
+}
+
+#[test]
+fn mocknet_chain_fingerprint_tracks_mined_ops() {
+    let fingerprint_of_run = |keychain_seed: u8| {
+        let mut controller = make_mocknet_controller(Config::default());
+        let mut keychain = make_keychain(keychain_seed);
+        let mut op_signer = keychain.generate_op_signer();
+        for _ in 0..3 {
+            let tip = controller.get_chain_tip();
+            let op = make_leader_key_register(&mut keychain, &tip);
+            controller.submit_operation(op, &mut op_signer);
+            controller.sync();
+        }
+        controller.chain_fingerprint().unwrap()
+    };
+
+    assert_eq!(fingerprint_of_run(60), fingerprint_of_run(60));
+    assert!(fingerprint_of_run(60) != fingerprint_of_run(61));
+}
+
+/// Mine 5 blocks in the DB of `conf`, corrupting the canonical block at `corrupt_height` if given.
+fn mine_resumable_chain(conf: &Config, corrupt_height: Option<u64>) {
+    let mut controller = make_mocknet_controller(conf.clone());
+    for _ in 0..5 {
+        controller.sync();
+    }
+    if let Some(height) = corrupt_height {
+        corrupt_parent_hash(&mut controller, height);
+    }
+}
+
+#[test]
+fn mocknet_verify_on_resume_accepts_healthy_chain() {
+    let mut conf = Config::default();
+    conf.mocknet.verify_on_resume = true;
+    mine_resumable_chain(&conf, None);
+
+    let mut controller = make_mocknet_controller(conf);
+    assert_eq!(controller.get_chain_tip().block_snapshot.block_height, 5);
+    assert!(controller.verify_chain().is_ok());
+}
+
+#[test]
+fn mocknet_resume_without_verification_trusts_stored_tip() {
+    let conf = Config::default();
+    mine_resumable_chain(&conf, Some(3));
+
+    let mut controller = make_mocknet_controller(conf);
+    assert_eq!(controller.get_chain_tip().block_snapshot.block_height, 5);
+    match controller.verify_chain() {
+        Err(BurnchainControllerError::InvalidState(msg)) => assert!(msg.contains("height 3")),
+        other => panic!("expected the tampered chain to fail verification, got {:?}", other)
+    }
+}
+
+#[test]
+fn mocknet_verify_on_resume_rejects_tampered_chain() {
+    let mut conf = Config::default();
+    conf.mocknet.verify_on_resume = true;
+    mine_resumable_chain(&conf, Some(3));
+
+    let mut controller = MocknetController::new(conf.clone());
+    match controller.try_start() {
+        Err(BurnchainControllerError::InvalidState(msg)) => assert!(msg.contains("height 3")),
+        other => panic!("expected the tampered chain to fail verification, got {:?}", other)
+    }
+
+    let mut controller = MocknetController::new(conf);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| controller.start()));
+    assert!(result.is_err());
+}
+
+#[test]
+fn mocknet_queued_by_type_splits_the_queue() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(70);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key_tip = controller.sync();
+    let key = find_leader_key(&key_tip, &public_key);
+
+    let first_key = make_leader_key_register(&mut keychain, &key_tip);
+    let second_key = make_leader_key_register(&mut keychain, &key_tip);
+    let queued_keys = vec![leader_key_public_key(&first_key), leader_key_public_key(&second_key)];
+    controller.submit_operation(first_key, &mut op_signer);
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    controller.submit_operation(commit, &mut op_signer);
+    controller.submit_operation(second_key, &mut op_signer);
+    let user_burn = BlockstackOperationType::UserBurnSupport(UserBurnSupportOp {
+        address: keychain.get_address(),
+        consensus_hash: key_tip.block_snapshot.consensus_hash.clone(),
+        public_key: key.public_key.clone(),
+        key_block_ptr: key.block_height as u32,
+        key_vtxindex: key.vtxindex as u16,
+        block_header_hash_160: Hash160([2u8; 20]),
+        burn_fee: 500,
+        txid: Txid([0u8; 32]),
+        vtxindex: 0,
+        block_height: 0,
+        burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+    });
+    controller.submit_operation(user_burn, &mut op_signer);
+
+    let queued = controller.queued_by_type();
+    let public_keys: Vec<_> = queued.leader_key_registers.iter().map(|op| op.public_key.clone()).collect();
+    assert_eq!(public_keys, queued_keys);
+    assert_eq!(queued.block_commits.len(), 1);
+    assert_eq!(queued.block_commits[0].block_header_hash, BlockHeaderHash([1u8; 32]));
+    assert_eq!(queued.user_burns.len(), 1);
+    assert_eq!(queued.user_burns[0].burn_fee, 500);
+
+    controller.sync();
+    let queued = controller.queued_by_type();
+    assert!(queued.leader_key_registers.is_empty() && queued.block_commits.is_empty() && queued.user_burns.is_empty());
+}
+
+#[test]
+fn mocknet_round_robin_sortition_rotates_winners() {
+    let mut conf = Config::default();
+    conf.mocknet.sortition_mode = SortitionMode::RoundRobin;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut miners = vec![make_keychain(80), make_keychain(81), make_keychain(82)];
+    let mut winners = vec![];
+    for round in 0..6u8 {
+        let tip = controller.get_chain_tip();
+        let mut public_keys = vec![];
+        for miner in miners.iter_mut() {
+            let op = make_leader_key_register(miner, &tip);
+            public_keys.push(leader_key_public_key(&op));
+            controller.submit_operation(op, &mut miner.generate_op_signer());
+        }
+        let key_tip = controller.sync();
+
+        for (i, miner) in miners.iter().enumerate() {
+            let key = find_leader_key(&key_tip, &public_keys[i]);
+            let commit = make_block_commit(miner, &key, BlockHeaderHash([round * 3 + i as u8 + 1; 32]), 1000 * (3 - i as u64));
+            controller.submit_operation(commit, &mut miner.generate_op_signer());
+        }
+        let commit_tip = controller.sync();
+        assert!(commit_tip.block_snapshot.sortition);
+
+        let winner = commit_tip.state_transition.accepted_ops.iter()
+            .filter_map(|op| match op {
+                BlockstackOperationType::LeaderBlockCommit(commit) if commit.txid == commit_tip.block_snapshot.winning_block_txid => Some(commit.input.clone()),
+                _ => None
+            })
+            .next()
+            .expect("no winning commit");
+        winners.push(miners.iter().position(|miner| miner.get_burnchain_signer() == winner).unwrap());
+    }
+    assert_eq!(winners, vec![0, 1, 2, 0, 1, 2]);
+}
+
+#[test]
+fn mocknet_estimated_memory_grows_with_queue_and_annotations() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+    let baseline = controller.estimated_memory_bytes();
+
+    let mut keychain = make_keychain(90);
+    let mut op_signer = keychain.generate_op_signer();
+    for _ in 0..10 {
+        let tip = controller.get_chain_tip();
+        let op = make_leader_key_register(&mut keychain, &tip);
+        controller.submit_operation(op, &mut op_signer);
+    }
+    let with_queue = controller.estimated_memory_bytes();
+    assert!(with_queue >= baseline + 10 * std::mem::size_of::<BlockstackOperationType>());
+
+    controller.annotate_block(1, "x".repeat(4096));
+    assert!(controller.estimated_memory_bytes() >= with_queue + 4096);
+}
+
+#[test]
+fn mocknet_refuses_commits_on_future_keys() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(100);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+    let tip_height = controller.get_chain_tip().block_snapshot.block_height;
+
+    let mut future_key = key.clone();
+    future_key.block_height = tip_height + 1;
+    let commit = make_block_commit(&keychain, &future_key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Err(RejectReason::FutureKeyReference));
+
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+}
+
+#[test]
+fn mocknet_bulk_import_ops_fills_gaps() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+    let tip = controller.get_chain_tip();
+
+    let mut keychain = make_keychain(110);
+    let ops: Vec<_> = [5, 2, 5, 8].iter()
+        .map(|height| (*height, make_leader_key_register(&mut keychain, &tip)))
+        .collect();
+    let expected: Vec<_> = ops.iter().map(|(height, op)| (*height, leader_key_public_key(op))).collect();
+
+    let new_tip = controller.bulk_import_ops(ops).unwrap();
+    assert_eq!(new_tip.block_snapshot.block_height, 8);
+    assert_eq!(controller.get_chain_tip().block_snapshot.burn_header_hash, new_tip.block_snapshot.burn_header_hash);
+
+    let tip_sortition_id = new_tip.block_snapshot.sortition_id;
+    for height in 2..9 {
+        let keys: Vec<_> = {
+            let ic = controller.sortdb_ref().index_conn();
+            let snapshot = SortitionDB::get_ancestor_snapshot(&ic, height, &tip_sortition_id).unwrap().unwrap();
+            SortitionDB::get_leader_keys_by_block(controller.sortdb_ref().conn(), &snapshot.sortition_id).unwrap()
+                .into_iter().map(|key| key.public_key).collect()
+        };
+        let expected_keys: Vec<_> = expected.iter()
+            .filter(|(expected_height, _)| *expected_height == height)
+            .map(|(_, public_key)| public_key.clone())
+            .collect();
+        assert_eq!(keys, expected_keys, "leader keys at height {}", height);
+    }
+
+    let tip = controller.get_chain_tip();
+    let stale = vec![(8, make_leader_key_register(&mut keychain, &tip))];
+    match controller.bulk_import_ops(stale) {
+        Err(BurnchainControllerError::InvalidState(_)) => {},
+        other => panic!("expected importing below the tip to fail, got {:?}", other.map(|tip| tip.block_snapshot.block_height))
+    }
+}
+
+#[test]
+fn mocknet_address_book_is_deterministic() {
+    let controller = MocknetController::new(Config::default());
+    let alice = controller.address_for("alice");
+    assert_eq!(controller.address_for("alice"), alice);
+    assert!(controller.address_for("bob") != alice);
+    assert_eq!(controller.keychain_for("alice").get_address(), alice);
+
+    let other_run = MocknetController::new(Config::default());
+    assert_eq!(other_run.address_for("alice"), alice);
+    assert_eq!(other_run.address_for("bob"), controller.address_for("bob"));
+
+    let mut conf = Config::default();
+    conf.mocknet.chain_seed = b"another chain".to_vec();
+    assert!(MocknetController::new(conf).address_for("alice") != alice);
+}
+
+#[test]
+fn mocknet_catch_up_releases_held_blocks_in_order() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+    assert!(controller.mine_held(vec![]).is_err());
+
+    controller.hold_tip();
+    let held_tip = controller.get_chain_tip().block_snapshot;
+    for _ in 0..3 {
+        controller.mine_held(vec![]).unwrap();
+    }
+    assert_eq!(controller.get_chain_tip().block_snapshot, held_tip);
+
+    let tips = controller.catch_up().unwrap();
+    let heights: Vec<_> = tips.iter().map(|tip| tip.block_snapshot.block_height).collect();
+    assert_eq!(heights, vec![held_tip.block_height + 1, held_tip.block_height + 2, held_tip.block_height + 3]);
+    assert_eq!(tips[0].block_snapshot.parent_burn_header_hash, held_tip.burn_header_hash);
+    for pair in tips.windows(2) {
+        assert_eq!(pair[1].block_snapshot.parent_burn_header_hash, pair[0].block_snapshot.burn_header_hash);
+    }
+    assert_eq!(controller.get_chain_tip().block_snapshot, tips[2].block_snapshot);
+
+    assert!(controller.catch_up().unwrap().is_empty());
+    assert!(controller.mine_held(vec![]).is_err());
+}
+
+#[test]
+fn mocknet_commit_against_snapshot_forks_at_the_anchor() {
+    let mut controller = make_mocknet_controller(Config::default());
+    controller.sync();
+
+    let mut miner = MockMiner::new(make_keychain(120), 1000);
+    miner.auto_renew_keys = true;
+    miner.simulate_round(&mut controller);
+    let mut tip = None;
+    for _ in 0..4 {
+        tip = Some(miner.simulate_round(&mut controller));
+    }
+    let tip = tip.unwrap().block_snapshot;
+    let anchor = controller.parent_snapshot(&controller.parent_snapshot(&tip).unwrap().unwrap()).unwrap().unwrap();
+    assert!(anchor.sortition);
+    let anchor_commit = controller.winning_commit_at(&anchor).unwrap().unwrap();
+
+    let commit = miner.against_snapshot(&controller, &anchor).unwrap().expect("miner has no key available");
+    let mut op_signer = make_keychain(120).generate_op_signer();
+    assert_eq!(controller.try_submit_operation(commit, &mut op_signer), Ok(()));
+    let forked = controller.sync().block_snapshot;
+    assert!(forked.sortition);
+
+    // the new Stacks block builds on the anchor, while the previous tip's built on its parent
+    let forked_commit = controller.winning_commit_at(&forked).unwrap().unwrap();
+    assert_eq!((forked_commit.parent_block_ptr as u64, forked_commit.parent_vtxindex as u32),
+               (anchor.block_height, anchor_commit.vtxindex));
+    let tip_commit = controller.winning_commit_at(&tip).unwrap().unwrap();
+    assert_eq!(tip_commit.parent_block_ptr as u64, tip.block_height - 1);
+}
+
+#[test]
+fn mocknet_next_burn_target_averages_recent_burns() {
+    let mut conf = Config::default();
+    conf.mocknet.burn_target = 777;
+    let mut constant = make_mocknet_controller(conf.clone());
+    constant.sync();
+    assert_eq!(constant.next_burn_target().unwrap(), 777);
+
+    conf.mocknet.burn_target_window = Some(3);
+    // a fresh sortition DB, rather than resuming the chain mined above
+    conf.node.working_dir = Config::default().node.working_dir;
+    let mut controller = make_mocknet_controller(conf);
+    assert_eq!(controller.next_burn_target().unwrap(), 777);
+
+    let mut keychain = make_keychain(130);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let mut public_keys = vec![];
+    for _ in 0..4 {
+        let op = make_leader_key_register(&mut keychain, &tip);
+        public_keys.push(leader_key_public_key(&op));
+        controller.submit_operation(op, &mut op_signer);
+    }
+    let key_tip = controller.sync();
+    // one block burning nothing so far
+    assert_eq!(controller.next_burn_target().unwrap(), 0);
+
+    let burns = [1000, 4000, 7000, 10000];
+    for (i, burn) in burns.iter().enumerate() {
+        let key = find_leader_key(&key_tip, &public_keys[i]);
+        let commit = make_block_commit(&keychain, &key, BlockHeaderHash([i as u8 + 1; 32]), *burn);
+        controller.submit_operation(commit, &mut op_signer);
+        controller.sync();
+    }
+    assert_eq!(controller.next_burn_target().unwrap(), (4000 + 7000 + 10000) / 3);
+}
+
+#[test]
+fn mocknet_processing_log_path_appends_json_lines() {
+    let mut conf = Config::default();
+    let log_path = PathBuf::from(&conf.node.working_dir).join("processing-log.jsonl");
+    conf.mocknet.processing_log_path = Some(log_path.to_str().unwrap().to_string());
+    conf.mocknet.processing_log_capacity = 0;
+    let mut controller = make_mocknet_controller(conf);
+
+    let mut keychain = make_keychain(140);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let op = make_leader_key_register(&mut keychain, &tip);
+    let public_key = leader_key_public_key(&op);
+    controller.submit_operation(op, &mut op_signer);
+    let key = find_leader_key(&controller.sync(), &public_key);
+
+    let mut missing_key = key.clone();
+    missing_key.vtxindex = 99;
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    let invalid_commit = make_block_commit(&keychain, &missing_key, BlockHeaderHash([2u8; 32]), 1000);
+    controller.submit_operation(commit, &mut op_signer);
+    controller.submit_operation(invalid_commit, &mut op_signer);
+    controller.sync();
+    controller.sync();
+
+    let contents = std::fs::read_to_string(&log_path).unwrap();
+    let records: Vec<OpProcessingRecord> = contents.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let outcomes: Vec<_> = records.iter()
+        .map(|record| (record.block_height, record.accepted))
+        .collect();
+    assert_eq!(outcomes, vec![(1, true), (2, true), (2, false)]);
+    assert!(controller.processing_log().is_empty());
+}
+
+/// Records `is_reorging` as seen by an observer each time a block is mined.
+struct ReorgFlagSink {
+    watch: ReorgWatch,
+    observed: Arc<Mutex<Vec<(u64, bool)>>>,
+}
+
+impl OpSink for ReorgFlagSink {
+    fn on_ops_mined(&mut self, height: u64, _ops: &[BlockstackOperationType]) {
+        self.observed.lock().unwrap().push((height, self.watch.is_reorging()));
+    }
+}
+
+#[test]
+fn mocknet_is_reorging_during_reorg_only() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let observed = Arc::new(Mutex::new(vec![]));
+    let watch = controller.reorg_watch();
+    controller.add_op_sink(Box::new(ReorgFlagSink { watch: watch.clone(), observed: observed.clone() }));
+
+    controller.sync();
+    controller.sync();
+    assert!(!controller.is_reorging());
+    controller.reorg(1, vec![vec![], vec![]]).unwrap();
+    assert!(!controller.is_reorging() && !watch.is_reorging());
+    controller.sync();
+
+    assert_eq!(*observed.lock().unwrap(), vec![(1, false), (2, false), (2, true), (3, true), (4, false)]);
+}
+
+struct PanickingSink {
+    armed: Arc<Mutex<bool>>,
+}
+
+impl OpSink for PanickingSink {
+    fn on_ops_mined(&mut self, _height: u64, _ops: &[BlockstackOperationType]) {
+        if *self.armed.lock().unwrap() {
+            panic!("sink failure");
+        }
+    }
+}
+
+#[test]
+fn mocknet_reorg_flag_cleared_when_reorg_panics() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let armed = Arc::new(Mutex::new(false));
+    controller.add_op_sink(Box::new(PanickingSink { armed: armed.clone() }));
+    let watch = controller.reorg_watch();
+
+    controller.sync();
+    controller.sync();
+    *armed.lock().unwrap() = true;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        controller.reorg(1, vec![vec![], vec![]])
+    }));
+    assert!(result.is_err());
+    assert!(!controller.is_reorging() && !watch.is_reorging());
+}
+
+#[test]
+fn mocknet_finalization_observer_fires_once_per_block() {
+    let mut conf = Config::default();
+    conf.mocknet.stability_depth = 2;
+    let mut controller = make_mocknet_controller(conf);
+    controller.sync();
+
+    let finalized = Arc::new(Mutex::new(vec![]));
+    let observed = finalized.clone();
+    controller.set_finalization_observer(Box::new(move |snapshot| {
+        observed.lock().unwrap().push(snapshot.block_height);
+    }));
+
+    controller.sync();
+    assert_eq!(*finalized.lock().unwrap(), vec![0]);
+    for height in 3..8 {
+        controller.sync();
+        let expected: Vec<u64> = (0..(height - 1)).collect();
+        assert_eq!(*finalized.lock().unwrap(), expected, "after mining block {}", height);
+    }
+}
+
+#[test]
+fn mocknet_finalization_observer_reports_replacements_after_deep_reorg() {
+    let mut conf = Config::default();
+    conf.mocknet.stability_depth = 2;
+    let mut controller = make_mocknet_controller(conf);
+
+    let finalized = Arc::new(Mutex::new(vec![]));
+    let observed = finalized.clone();
+    controller.set_finalization_observer(Box::new(move |snapshot| {
+        observed.lock().unwrap().push((snapshot.block_height, snapshot.burn_header_hash));
+    }));
+    for _ in 0..6 {
+        controller.sync();
+    }
+    let replaced = finalized.lock().unwrap()[4].1;
+
+    // the reorg alone makes the replacement blocks at heights 4 and 5 final
+    let new_tip = controller.reorg(3, vec![vec![], vec![], vec![], vec![]]).unwrap();
+    let heights: Vec<u64> = finalized.lock().unwrap().iter().map(|(height, _)| *height).collect();
+    assert_eq!(heights, vec![0, 1, 2, 3, 4, 4, 5]);
+    let mut replacement = new_tip.block_snapshot.clone();
+    while replacement.block_height > 4 {
+        replacement = controller.parent_snapshot(&replacement).unwrap().unwrap();
+    }
+    assert_eq!(finalized.lock().unwrap()[5].1, replacement.burn_header_hash);
+    assert!(replacement.burn_header_hash != replaced);
+
+    let op = make_leader_key_register(&mut make_keychain(42), &new_tip);
+    controller.bulk_import_ops(vec![(9, op)]).unwrap();
+    let heights: Vec<u64> = finalized.lock().unwrap().iter().map(|(height, _)| *height).collect();
+    assert_eq!(heights, vec![0, 1, 2, 3, 4, 4, 5, 6, 7]);
+}
+
+#[test]
+fn mocknet_miner_win_stats_follow_burn_ratio() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let rounds = 60;
+    let mut miners = vec![make_keychain(150), make_keychain(151)];
+    let burns = [3000, 1000];
+
+    let tip = controller.get_chain_tip();
+    let mut public_keys = vec![vec![], vec![]];
+    for _ in 0..rounds {
+        for (i, miner) in miners.iter_mut().enumerate() {
+            let op = make_leader_key_register(miner, &tip);
+            public_keys[i].push(leader_key_public_key(&op));
+            controller.submit_operation(op, &mut miner.generate_op_signer());
+        }
+    }
+    let key_tip = controller.sync();
+
+    for round in 0..rounds {
+        for (i, miner) in miners.iter().enumerate() {
+            let key = find_leader_key(&key_tip, &public_keys[i][round]);
+            let mut block_header_hash = [i as u8 + 1; 32];
+            block_header_hash[..8].copy_from_slice(&(round as u64).to_be_bytes());
+            let commit = make_block_commit(miner, &key, BlockHeaderHash(block_header_hash), burns[i]);
+            controller.submit_operation(commit, &mut miner.generate_op_signer());
+        }
+        assert!(controller.sync().block_snapshot.sortition);
+    }
+
+    let stats = controller.miner_win_stats().unwrap();
+    let wins: Vec<u64> = miners.iter().map(|miner| *stats.get(&miner.get_address()).unwrap_or(&0)).collect();
+    assert_eq!(wins[0] + wins[1], rounds as u64);
+    let share = wins[0] as f64 / rounds as f64;
+    assert!(share > 0.58 && share < 0.92, "heavier miner won {} of {} sortitions", wins[0], rounds);
+}
+
+#[test]
+fn mocknet_submission_policy_defers_every_second_op() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let consultations = Arc::new(Mutex::new(0u64));
+    let counter = consultations.clone();
+    controller.set_submission_policy(Box::new(move |_op| {
+        let mut count = counter.lock().unwrap();
+        *count += 1;
+        if *count % 2 == 0 { SubmitDecision::Defer } else { SubmitDecision::Accept }
+    }));
+
+    let mut keychain = make_keychain(160);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let ops: Vec<_> = (0..4).map(|_| make_leader_key_register(&mut keychain, &tip)).collect();
+    let public_keys: Vec<_> = ops.iter().map(leader_key_public_key).collect();
+    for op in ops.into_iter() {
+        assert!(controller.submit_operation(op, &mut op_signer));
+    }
+    let deferred: Vec<_> = controller.deferred_operations().iter().map(leader_key_public_key).collect();
+    assert_eq!(deferred, vec![public_keys[1].clone(), public_keys[3].clone()]);
+
+    // the second op is accepted on reconsideration, the fourth deferred again
+    let mined: Vec<_> = controller.sync().state_transition.accepted_ops.iter().map(leader_key_public_key).collect();
+    assert_eq!(mined, vec![public_keys[0].clone(), public_keys[2].clone(), public_keys[1].clone()]);
+    let deferred: Vec<_> = controller.deferred_operations().iter().map(leader_key_public_key).collect();
+    assert_eq!(deferred, vec![public_keys[3].clone()]);
+
+    let mined: Vec<_> = controller.sync().state_transition.accepted_ops.iter().map(leader_key_public_key).collect();
+    assert_eq!(mined, vec![public_keys[3].clone()]);
+    assert!(controller.deferred_operations().is_empty());
+    assert_eq!(*consultations.lock().unwrap(), 7);
+}
+
+#[test]
+fn mocknet_inclusion_latency_histogram_reflects_spillover() {
+    let mut conf = Config::default();
+    conf.mocknet.max_ops_per_block = Some(2);
+    let mut controller = make_mocknet_controller(conf);
+    controller.sync();
+    assert!(controller.inclusion_latency_histogram().is_empty());
+
+    let mut keychain = make_keychain(170);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    for _ in 0..5 {
+        let op = make_leader_key_register(&mut keychain, &tip);
+        controller.submit_operation(op, &mut op_signer);
+    }
+    for _ in 0..3 {
+        controller.sync();
+    }
+
+    let histogram: Vec<_> = controller.inclusion_latency_histogram().into_iter().collect();
+    assert_eq!(histogram, vec![(1, 2), (2, 2), (3, 1)]);
+}
+
+#[test]
+fn mocknet_reorg_schedule_reorgs_at_the_scheduled_height() {
+    let mut conf = Config::default();
+    conf.mocknet.reorg_schedule = vec![ScheduledReorg { at_height: 5, depth: 2, alt_blocks: vec![vec![]; 3] }];
+    let mut controller = make_mocknet_controller(conf);
+    for _ in 0..3 {
+        controller.sync();
+    }
+    let fork_point = controller.get_chain_tip().block_snapshot;
+    let orphaned = controller.sync().block_snapshot;
+    assert_eq!(orphaned.block_height, 4);
+
+    // mining height 5 replaces heights 4 and 5 with the three alternate blocks
+    let reorged_tip = controller.sync().block_snapshot;
+    assert_eq!(reorged_tip.block_height, 6);
+    assert_eq!(controller.get_chain_tip().block_snapshot, reorged_tip);
+    assert_eq!(controller.common_ancestor(&orphaned, &reorged_tip).unwrap(), Some(fork_point));
+
+    // each scheduled reorg runs once
+    assert_eq!(controller.sync().block_snapshot.block_height, 7);
+}
+
+#[test]
+fn mocknet_validate_full_chain_finds_injected_op() {
+    let mut controller = make_mocknet_controller(Config::default());
+    let mut keychain = make_keychain(180);
+    let mut op_signer = keychain.generate_op_signer();
+    let tip = controller.get_chain_tip();
+    let ops = vec![make_leader_key_register(&mut keychain, &tip), make_leader_key_register(&mut keychain, &tip)];
+    let public_keys: Vec<_> = ops.iter().map(leader_key_public_key).collect();
+    for op in ops.into_iter() {
+        assert!(controller.submit_operation(op, &mut op_signer));
+    }
+    let key_tip = controller.sync();
+
+    let key = find_leader_key(&key_tip, &public_keys[0]);
+    let commit = make_block_commit(&keychain, &key, BlockHeaderHash([1u8; 32]), 1000);
+    assert!(controller.submit_operation(commit, &mut op_signer));
+    controller.sync();
+    controller.sync();
+    controller.validate_full_chain().unwrap();
+
+    // bulk imports skip the submission checks, so a zero-seed commit gets mined
+    let key = find_leader_key(&key_tip, &public_keys[1]);
+    let mut commit = make_block_commit(&keychain, &key, BlockHeaderHash([2u8; 32]), 1000);
+    if let BlockstackOperationType::LeaderBlockCommit(ref mut op) = commit {
+        op.new_seed = VRFSeed([0u8; 32]);
+    }
+    let commit_tip = controller.bulk_import_ops(vec![(4, commit)]).unwrap();
+    assert_eq!(commit_tip.state_transition.accepted_ops.len(), 1);
+    controller.sync();
+
+    match controller.validate_full_chain() {
+        Err(ChainValidationError::InvalidOperation { block_height, rule, .. }) => {
+            assert_eq!(block_height, 4);
+            assert_eq!(rule, ValidationRule::ZeroSeed);
+        },
+        other => panic!("expected an invalid operation, got {:?}", other)
+    }
+}
@@ -2,6 +2,7 @@ mod neon_integrations;
 mod integrations;
 mod bitcoin_regtest;
 mod mempool;
+mod mocknet;
 
 use stacks::chainstate::stacks::events::{StacksTransactionEvent, STXEventType};
 use stacks::chainstate::stacks::{TransactionPayload, StacksTransactionSigner, StacksPublicKey,TransactionPostConditionMode, TransactionSmartContract, TransactionAuth,TransactionVersion, C32_ADDRESS_VERSION_TESTNET_SINGLESIG,
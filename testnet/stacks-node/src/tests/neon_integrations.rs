@@ -31,7 +31,7 @@ fn neon_integration_test_conf() -> (Config, StacksAddress) {
     conf.burnchain.password = Some("neon-tester-pass".into());
     conf.burnchain.peer_host = "127.0.0.1".into();
     conf.burnchain.local_mining_public_key = Some(keychain.generate_op_signer()
-        .get_public_key().to_hex());
+        .get_public_key().unwrap().to_hex());
     conf.burnchain.commit_anchor_block_within = 0;
 
     let miner_account = keychain.origin_address().unwrap();